@@ -1,5 +1,8 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt::Display, fs};
+use std::path::{Path, PathBuf};
 use egui_extras::RetainedImage;
+use egui_extras::image::{load_svg_bytes_with_size, FitTo};
+use image::{DynamicImage, RgbaImage};
 use rusttype::Font;
 use serde::{Serialize,Deserialize};
 
@@ -44,6 +47,30 @@ impl Display for KeyCommand {
     }
 }
 
+/// Named list of colors saved in the settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<[u8; 4]>,
+}
+
+/// Built-in stickers: icon name, tooltip and svg
+pub const STICKERS: [(&str, &str, &[u8]); 4] = [
+    ("sticker-check", "Check", include_bytes!("../../resources/stickers/check.svg")),
+    ("sticker-cross", "Cross", include_bytes!("../../resources/stickers/cross.svg")),
+    ("sticker-warning", "Warning", include_bytes!("../../resources/stickers/warning.svg")),
+    ("sticker-info", "Info", include_bytes!("../../resources/stickers/info.svg")),
+];
+
+/// Rasterize the sticker named [name] into an image of [size]x[size] pixels
+pub fn sticker_image(name: &str, size: u32) -> Option<DynamicImage> {
+    let (_, _, bytes) = STICKERS.iter().find(|(sticker, _, _)| *sticker == name)?;
+    let image = load_svg_bytes_with_size(bytes, FitTo::Size(size, size)).ok()?;
+    let [width, height] = image.size;
+    let pixels = image.pixels.iter().flat_map(|c| c.to_srgba_unmultiplied()).collect();
+    RgbaImage::from_raw(width as u32, height as u32, pixels).map(DynamicImage::ImageRgba8)
+}
+
 /// Load in the application state the svg icons as RetainedImage, and also the correspondence between the backend name of the icon and its tooltip.
 pub fn load_icons() -> (HashMap<String, Result<RetainedImage, String>>, HashMap<String, String>, ) {
     let mut icons_map = HashMap::new();
@@ -77,6 +104,16 @@ pub fn load_icons() -> (HashMap<String, Result<RetainedImage, String>>, HashMap<
         ),
     );
     tooltips_map.insert("circle-fill".to_string(), "Filled Circle".to_string());
+    icons_map.insert(
+        "ellipse".to_string(),
+        RetainedImage::from_svg_bytes("ellipse", include_bytes!("../../resources/ellipse.svg")),
+    );
+    tooltips_map.insert("ellipse".to_string(), "Hollow Ellipse".to_string());
+    icons_map.insert(
+        "ellipse-fill".to_string(),
+        RetainedImage::from_svg_bytes("ellipse-fill", include_bytes!("../../resources/ellipse-fill.svg")),
+    );
+    tooltips_map.insert("ellipse-fill".to_string(), "Filled Ellipse".to_string());
     icons_map.insert(
         "circle".to_string(),
         RetainedImage::from_svg_bytes("circle", include_bytes!("../../resources/circle.svg")),
@@ -152,18 +189,285 @@ pub fn load_icons() -> (HashMap<String, Result<RetainedImage, String>>, HashMap<
         ),
     );
     tooltips_map.insert("fonts".to_string(), "Text".to_string());
+    icons_map.insert(
+        "chat-left-text".to_string(),
+        RetainedImage::from_svg_bytes("chat-left-text", include_bytes!("../../resources/chat-left-text.svg")),
+    );
+    tooltips_map.insert("chat-left-text".to_string(), "Callout".to_string());
+    icons_map.insert(
+        "zoom-in".to_string(),
+        RetainedImage::from_svg_bytes("zoom-in", include_bytes!("../../resources/zoom-in.svg")),
+    );
+    tooltips_map.insert("zoom-in".to_string(), "Magnifier".to_string());
+    icons_map.insert(
+        "eyedropper".to_string(),
+        RetainedImage::from_svg_bytes("eyedropper", include_bytes!("../../resources/eyedropper.svg")),
+    );
+    tooltips_map.insert("eyedropper".to_string(), "Pick a color from the image".to_string());
+    icons_map.insert(
+        "image".to_string(),
+        RetainedImage::from_svg_bytes("image", include_bytes!("../../resources/image.svg")),
+    );
+    tooltips_map.insert("image".to_string(), "Insert an image, a sticker or an emoji".to_string());
+    icons_map.insert(
+        "rulers".to_string(),
+        RetainedImage::from_svg_bytes("rulers", include_bytes!("../../resources/rulers.svg")),
+    );
+    tooltips_map.insert("rulers".to_string(), "Measure distances and color contrast".to_string());
+    icons_map.insert(
+        "spotlight".to_string(),
+        RetainedImage::from_svg_bytes("spotlight", include_bytes!("../../resources/spotlight.svg")),
+    );
+    tooltips_map.insert("spotlight".to_string(), "Spotlight: dim everything but the chosen areas".to_string());
+    icons_map.insert(
+        "fullscreen-exit".to_string(),
+        RetainedImage::from_svg_bytes("fullscreen-exit", include_bytes!("../../resources/fullscreen-exit.svg")),
+    );
+    tooltips_map.insert("fullscreen-exit".to_string(), "Trim: remove uniform borders around the image".to_string());
+    icons_map.insert(
+        "bounding-box-circles".to_string(),
+        RetainedImage::from_svg_bytes("bounding-box-circles", include_bytes!("../../resources/bounding-box-circles.svg")),
+    );
+    tooltips_map.insert("bounding-box-circles".to_string(), "Transform: rotate, flip or resize the image".to_string());
+    icons_map.insert(
+        "layers".to_string(),
+        RetainedImage::from_svg_bytes("layers", include_bytes!("../../resources/layers.svg")),
+    );
+    tooltips_map.insert("layers".to_string(), "Layers: show, hide and reorder the layers of the image".to_string());
+    icons_map.insert(
+        "sliders".to_string(),
+        RetainedImage::from_svg_bytes("sliders", include_bytes!("../../resources/sliders.svg")),
+    );
+    tooltips_map.insert("sliders".to_string(), "Adjust the brightness, contrast and colors of the capture".to_string());
+    for (name, tooltip, bytes) in STICKERS {
+        icons_map.insert(name.to_string(), RetainedImage::from_svg_bytes_with_size(name, bytes, FitTo::Size(32, 32)));
+        tooltips_map.insert(name.to_string(), tooltip.to_string());
+    }
+    icons_map.insert(
+        "arrow-clockwise".to_string(),
+        RetainedImage::from_svg_bytes(
+            "arrow-clockwise",
+            include_bytes!("../../resources/arrow-clockwise.svg"),
+        ),
+    );
+    tooltips_map.insert("arrow-clockwise".to_string(), "Rotate right".to_string());
+    icons_map.insert(
+        "arrow-counterclockwise".to_string(),
+        RetainedImage::from_svg_bytes(
+            "arrow-counterclockwise",
+            include_bytes!("../../resources/arrow-counterclockwise.svg"),
+        ),
+    );
+    tooltips_map.insert("arrow-counterclockwise".to_string(), "Rotate left".to_string());
     return (icons_map, tooltips_map);
 }
 
-pub fn load_fonts() -> HashMap<String, Option<Font<'static>>>{
-    let mut fonts = HashMap::new();
-    let font = Font::try_from_bytes(include_bytes!("../../resources/Roboto-Regular.ttf"));
-    fonts.insert("Roboto".to_string(), font);
-    let font = Font::try_from_bytes(include_bytes!("../../resources/Phudu-Regular.ttf"));
-    fonts.insert("Phudu".to_string(), font);
-    let font = Font::try_from_bytes(include_bytes!("../../resources/Montserrat-Regular.ttf"));
-    fonts.insert("Montserrat".to_string(), font);
-    let font = Font::try_from_bytes(include_bytes!("../../resources/OpenSans-Regular.ttf"));
-    fonts.insert("OpenSans".to_string(), font);
+/// Regular, bold and italic variants of a font. Only the regular one is required, missing variants are synthesized when drawing
+#[derive(Clone)]
+pub struct FontFamily {
+    pub regular: Option<Font<'static>>,
+    pub bold: Option<Font<'static>>,
+    pub italic: Option<Font<'static>>,
+    pub bold_italic: Option<Font<'static>>,
+}
+
+impl FontFamily {
+    /// Font to use for the requested style, together with the flags telling if bold and italic must be synthesized
+    pub fn variant(&self, bold: bool, italic: bool) -> (Option<&Font<'static>>, bool, bool) {
+        let exact = match (bold, italic) {
+            (true, true) => self.bold_italic.as_ref(),
+            (true, false) => self.bold.as_ref(),
+            (false, true) => self.italic.as_ref(),
+            (false, false) => self.regular.as_ref(),
+        };
+        match exact {
+            Some(font) => (Some(font), false, false),
+            //Prefer a real bold (or italic) and synthesize only the other style
+            None if bold && italic && self.bold.is_some() => (self.bold.as_ref(), false, true),
+            None if bold && italic && self.italic.is_some() => (self.italic.as_ref(), true, false),
+            None => (self.regular.as_ref(), bold, italic),
+        }
+    }
+}
+
+/// Where the data of a font face comes from: one of the fonts shipped with the application or a file on disk
+#[derive(Clone)]
+pub enum FontSource {
+    Embedded(&'static [u8]),
+    //Path of the file and index of the face inside it (font collections contain more than one)
+    File(PathBuf, u32),
+}
+
+impl FontSource {
+    pub fn load(&self) -> Option<Font<'static>> {
+        match self {
+            FontSource::Embedded(bytes) => Font::try_from_bytes(bytes),
+            FontSource::File(path, index) => Font::try_from_vec_and_index(fs::read(path).ok()?, *index),
+        }
+    }
+}
+
+/// Faces found for a font family. They are only parsed when the family is selected, to keep the startup fast
+#[derive(Clone, Default)]
+pub struct FontFaces {
+    pub regular: Option<FontSource>,
+    pub bold: Option<FontSource>,
+    pub italic: Option<FontSource>,
+    pub bold_italic: Option<FontSource>,
+}
+
+impl FontFaces {
+    fn embedded(bytes: &'static [u8]) -> Self {
+        FontFaces { regular: Some(FontSource::Embedded(bytes)), ..Default::default() }
+    }
+
+    fn add(&mut self, source: FontSource, bold: bool, italic: bool) {
+        let slot = match (bold, italic) {
+            (true, true) => &mut self.bold_italic,
+            (true, false) => &mut self.bold,
+            (false, true) => &mut self.italic,
+            (false, false) => &mut self.regular,
+        };
+        //Keep the first face found, user fonts are scanned before the system ones
+        if slot.is_none() {
+            *slot = Some(source);
+        }
+    }
+
+    /// Load the faces of the family. If there is no regular face, the closest one found is used in its place
+    pub fn load(&self) -> FontFamily {
+        let load = |source: &Option<FontSource>| source.as_ref().and_then(|s| s.load());
+        let mut family = FontFamily {
+            regular: load(&self.regular),
+            bold: load(&self.bold),
+            italic: load(&self.italic),
+            bold_italic: load(&self.bold_italic),
+        };
+        if family.regular.is_none() {
+            family.regular = family.italic.clone().or(family.bold.clone()).or(family.bold_italic.clone());
+        }
+        family
+    }
+}
+
+/// Font the text tool uses when no other font has been chosen
+pub const DEFAULT_FONT: &str = "Roboto";
+
+pub fn default_font() -> FontFamily {
+    FontFaces::embedded(include_bytes!("../../resources/Roboto-Regular.ttf")).load()
+}
+
+/// Directories where the operating system keeps the installed fonts
+fn system_font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        let windir = std::env::var_os("WINDIR").map(PathBuf::from).unwrap_or(PathBuf::from("C:\\Windows"));
+        dirs.push(windir.join("Fonts"));
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local).join("Microsoft").join("Windows").join("Fonts"));
+        }
+    } else if cfg!(target_os = "macos") {
+        dirs.push(PathBuf::from("/System/Library/Fonts"));
+        dirs.push(PathBuf::from("/Library/Fonts"));
+        if let Some(home) = home {
+            dirs.push(home.join("Library").join("Fonts"));
+        }
+    } else {
+        dirs.push(PathBuf::from("/usr/share/fonts"));
+        dirs.push(PathBuf::from("/usr/local/share/fonts"));
+        if let Some(home) = home {
+            dirs.push(home.join(".local").join("share").join("fonts"));
+            dirs.push(home.join(".fonts"));
+        }
+    }
+    dirs
+}
+
+/// Family name of a face, preferring the typographic family that groups all the weights together
+fn family_name(face: &ttf_parser::Face) -> Option<String> {
+    let find = |id: u16| face.names().into_iter().filter(|n| n.name_id == id).find_map(|n| n.to_string());
+    find(ttf_parser::name_id::TYPOGRAPHIC_FAMILY).or_else(|| find(ttf_parser::name_id::FAMILY))
+}
+
+/// Look recursively for font files in [dir] and add their faces to [fonts]. [visited] has the directories already scanned
+fn scan_font_dir(dir: &Path, fonts: &mut BTreeMap<String, FontFaces>, visited: &mut HashSet<PathBuf>) {
+    //Links can lead to a directory already scanned, or to one containing them, which would be scanned forever
+    match fs::canonicalize(dir) {
+        Ok(canonical) => {
+            if !visited.insert(canonical) {
+                return;
+            }
+        }
+        Err(_) => return,
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan_font_dir(&path, fonts, visited);
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        if !["ttf", "otf", "ttc", "otc"].contains(&extension.as_str()) {
+            continue;
+        }
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let faces = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+        for index in 0..faces {
+            if let Ok(face) = ttf_parser::Face::from_slice(&data, index) {
+                //The typographic family groups all the weights, only the regular and the bold ones fill the slots
+                let bold = match face.weight() {
+                    ttf_parser::Weight::Normal => false,
+                    ttf_parser::Weight::Bold => true,
+                    _ => continue,
+                };
+                if let Some(name) = family_name(&face) {
+                    fonts.entry(embedded_name(name)).or_default().add(FontSource::File(path.clone(), index), bold, face.is_italic());
+                }
+            }
+        }
+    }
+}
+
+/// Fonts shipped with the application, only their regular face. The bold and italic ones come from the installed fonts of the same family, if any
+const EMBEDDED_FONTS: [(&str, &[u8]); 4] = [
+    ("Roboto", include_bytes!("../../resources/Roboto-Regular.ttf")),
+    ("Phudu", include_bytes!("../../resources/Phudu-Regular.ttf")),
+    ("Montserrat", include_bytes!("../../resources/Montserrat-Regular.ttf")),
+    ("OpenSans", include_bytes!("../../resources/OpenSans-Regular.ttf")),
+];
+
+/// Name under which the faces of the family [name] are kept: the one of the embedded family it is, even if spaced differently ("Open Sans")
+fn embedded_name(name: String) -> String {
+    let compact = name.replace(' ', "");
+    match EMBEDDED_FONTS.iter().find(|(embedded, _)| embedded.eq_ignore_ascii_case(&compact)) {
+        Some((embedded, _)) => embedded.to_string(),
+        None => name,
+    }
+}
+
+/// Fonts shipped with the application, available before the ones on disk are found
+pub fn embedded_fonts() -> BTreeMap<String, FontFaces> {
+    EMBEDDED_FONTS.iter().map(|(name, bytes)| (name.to_string(), FontFaces::embedded(bytes))).collect()
+}
+
+/// Fonts available for the text tool: the embedded ones, the ones in [user_dir] and the ones installed in the system, sorted by name.
+/// Every font file is read, so it is meant to run on a worker thread
+pub fn load_fonts(user_dir: Option<&Path>) -> BTreeMap<String, FontFaces> {
+    let mut fonts = embedded_fonts();
+    let mut visited = HashSet::new();
+    if let Some(dir) = user_dir {
+        scan_font_dir(dir, &mut fonts, &mut visited);
+    }
+    for dir in system_font_dirs() {
+        scan_font_dir(&dir, &mut fonts, &mut visited);
+    }
     return fonts;
-}
\ No newline at end of file
+}
@@ -1,13 +1,20 @@
 use crate::gui::image_proc_extra_mod::*;
+use crate::gui::text_mod::TextBuffer;
+use crate::gui::config_mod::{default_font, FontFamily, DEFAULT_FONT};
+use std::cell::OnceCell;
 use std::cmp::max;
 use std::collections::VecDeque;
 use std::io::Cursor;
+use std::rc::Rc;
+use std::fmt::Display;
 use eframe::egui::Pos2;
-use egui::Rect;
-use image::DynamicImage;
+use egui::{ColorImage, Context, Rect, TextureHandle, TextureOptions, Vec2};
+use image::{DynamicImage, GenericImage, GrayImage, Luma, RgbaImage};
+use image::imageops::FilterType;
 use imageproc::drawing;
+use imageproc::drawing::Canvas;
 use png::Decoder;
-use rusttype::Font;
+use rusttype::{Font, Scale};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum Tool {
@@ -17,18 +24,73 @@ pub enum Tool {
     Arrow,
     HollowCircle,
     FilledCircle,
+    HollowEllipse,
+    FilledEllipse,
     Eraser,
     Crop,
     Highlighter,
     Text,
+    Callout,
+    Magnifier,
+    Eyedropper,
+    Insert,
+    Measure,
+    Spotlight,
     None,
 }
 
+impl Tool {
+    /// Tools drawing a shape between the point where the drag started and the pointer, which can be constrained and snapped
+    pub fn is_shape(&self) -> bool {
+        matches!(self, Tool::HollowRect | Tool::FilledRect | Tool::HollowCircle | Tool::FilledCircle | Tool::HollowEllipse | Tool::FilledEllipse | Tool::Arrow)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+/// Appearance of a text annotation, besides its color and size
+#[derive(Clone, PartialEq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub align: TextAlign,
+    //Filled rectangle drawn behind the text
+    pub background: bool,
+    pub background_color: [u8; 4],
+    pub padding: u32,
+    //Stroke drawn around the glyphs, to keep the text readable on busy screenshots
+    pub outline: bool,
+    pub outline_color: [u8; 4],
+    pub outline_width: u32,
+}
+
+impl TextStyle {
+    pub fn new() -> Self {
+        TextStyle {
+            bold: false,
+            italic: false,
+            align: TextAlign::Left,
+            background: false,
+            background_color: [255, 255, 255, 200],
+            padding: 6,
+            outline: false,
+            outline_color: [0, 0, 0, 255],
+            outline_width: 2,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TextManager {
     pub text_areas: Vec<TextManager>,
-    pub curr_font: Option<Font<'static>>,
-    pub curr_str: String,
+    pub curr_family: FontFamily,
+    pub style: TextStyle,
+    pub buffer: TextBuffer,
     pub writing: bool,
     pub edge: Pos2,
     pub width: f32,
@@ -39,20 +101,19 @@ pub struct TextManager {
     pub dirty: bool,
     //Needed since i rewrite everytime all the text on the screen during editing
     pub original_img: Image,
+    //Area of the image covered by the text and its textarea in the last preview
+    pub preview_area: Option<Rect>,
     pub curr_font_name: String,
-    pub cursor_x: usize,
-    pub cursor_y: usize,
 }
 
 impl TextManager {
-    pub fn new (_font: String, edge: Pos2, img: Image) -> TextManager {
-        let font_bytes = include_bytes!("../../resources/Roboto-Regular.ttf");
-        let font = Font::try_from_bytes(font_bytes);
+    pub fn new (font_name: String, family: FontFamily, edge: Pos2, img: Image) -> TextManager {
         TextManager{
             text_areas: Vec::new(),
-            curr_font: font,
-            curr_font_name: "Roboto".to_string(),
-            curr_str: "".to_string(),
+            curr_family: family,
+            style: TextStyle::new(),
+            curr_font_name: font_name,
+            buffer: TextBuffer::new(),
             curr_dim: 15,
             writing: false,
             edge,
@@ -61,15 +122,14 @@ impl TextManager {
             height:0.,
             dirty: false,
             original_img: img,
-            cursor_x:0,
-            cursor_y:0,
+            preview_area: None,
             max_height: 0.0,
         }
     }
 
     pub fn reset(&mut self) {
         self.text_areas.clear();
-        self.curr_str = "".to_string();
+        self.buffer.clear();
         self.curr_dim = 15;
         self.writing = false;
         self.max_width = 0.;
@@ -77,8 +137,42 @@ impl TextManager {
         self.width = 0.;
         self.height = 0.;
         self.dirty = false;
-        self.cursor_x = 0;
-        self.cursor_y = 0;
+        self.preview_area = None;
+    }
+
+    /// Font used to draw the text with the current style, and whether bold/italic must be synthesized
+    pub fn font(&self) -> (Option<&Font<'static>>, bool, bool) {
+        self.curr_family.variant(self.style.bold, self.style.italic)
+    }
+
+    pub fn has_font(&self) -> bool {
+        self.curr_family.regular.is_some()
+    }
+
+    /// Width of a line of text, drawn with the current font and style
+    pub fn measure(&self, text: &str) -> f32 {
+        let scale = Scale::uniform(self.curr_dim as f32);
+        match self.font() {
+            (Some(font), fake_bold, _) => {
+                let width = measure_line(font, text, scale).0;
+                if fake_bold && !text.is_empty() { width + fake_bold_offset(scale) } else { width }
+            }
+            (None, _, _) => 0.,
+        }
+    }
+
+    /// Horizontal position of a line inside a block of the given width, according to the alignment
+    pub fn line_offset(&self, text: &str, block_width: f32) -> f32 {
+        match self.style.align {
+            TextAlign::Left => 0.,
+            TextAlign::Center => (block_width - self.measure(text)) / 2.,
+            TextAlign::Right => block_width - self.measure(text),
+        }
+    }
+
+    /// Width of the widest line of the text
+    pub fn block_width(&self) -> f32 {
+        self.buffer.lines().iter().map(|l| self.measure(l)).fold(0., f32::max)
     }
 
     pub fn update_max_width(&mut self, rect: Rect) {
@@ -90,12 +184,1146 @@ impl TextManager {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum AspectRatio {
+    Free,
+    Square,
+    Wide,
+    Standard,
+    Custom,
+}
+
+impl Display for AspectRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AspectRatio::Free => "Free",
+            AspectRatio::Square => "1:1",
+            AspectRatio::Wide => "16:9",
+            AspectRatio::Standard => "4:3",
+            AspectRatio::Custom => "Custom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Part of the crop rectangle grabbed by the pointer
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum CropHandle {
+    TopLeft,
+    Top,
+    TopRight,
+    Right,
+    BottomRight,
+    Bottom,
+    BottomLeft,
+    Left,
+    Move,
+    //A new rectangle is being drawn
+    New,
+}
+
+/// Adjustable crop rectangle, expressed in image pixels. Nothing is cropped until [ImageStack::crop] is called with [rect]
+pub struct CropManager {
+    pub rect: Option<Rect>,
+    pub aspect: AspectRatio,
+    pub custom_ratio: (f32, f32),
+    pub dragging: Option<CropHandle>,
+    //When true, a click selects the window frame under the pointer instead of starting a rectangle
+    pub smart: bool,
+    pub smart_failed: bool,
+    //Where the pointer was pressed, and the rectangle at that moment
+    drag_origin: Pos2,
+    rect_origin: Rect,
+}
+
+impl CropManager {
+    pub fn new() -> Self {
+        CropManager {
+            rect: None,
+            aspect: AspectRatio::Free,
+            custom_ratio: (3., 2.),
+            dragging: None,
+            smart: false,
+            smart_failed: false,
+            drag_origin: Pos2::default(),
+            rect_origin: Rect::NOTHING,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.rect = None;
+        self.dragging = None;
+        self.smart_failed = false;
+    }
+
+    /// Width / height ratio imposed by [aspect], if any
+    pub fn ratio(&self) -> Option<f32> {
+        match self.aspect {
+            AspectRatio::Free => None,
+            AspectRatio::Square => Some(1.),
+            AspectRatio::Wide => Some(16. / 9.),
+            AspectRatio::Standard => Some(4. / 3.),
+            AspectRatio::Custom => Some(self.custom_ratio.0.max(1.) / self.custom_ratio.1.max(1.)),
+        }
+    }
+
+    /// Positions of the eight resize handles of the current rectangle
+    pub fn handles(&self) -> Vec<(CropHandle, Pos2)> {
+        match self.rect {
+            None => Vec::new(),
+            Some(r) => vec![
+                (CropHandle::TopLeft, r.left_top()),
+                (CropHandle::Top, r.center_top()),
+                (CropHandle::TopRight, r.right_top()),
+                (CropHandle::Right, r.right_center()),
+                (CropHandle::BottomRight, r.right_bottom()),
+                (CropHandle::Bottom, r.center_bottom()),
+                (CropHandle::BottomLeft, r.left_bottom()),
+                (CropHandle::Left, r.left_center()),
+            ],
+        }
+    }
+
+    /// Find what the pointer is over. [tolerance] is the grabbing distance from a handle, in image pixels
+    pub fn hit(&self, ptr: Pos2, tolerance: f32) -> CropHandle {
+        for (handle, pos) in self.handles() {
+            if pos.distance(ptr) <= tolerance {
+                return handle;
+            }
+        }
+        match self.rect {
+            Some(r) if r.contains(ptr) => CropHandle::Move,
+            _ => CropHandle::New,
+        }
+    }
+
+    pub fn start_drag(&mut self, ptr: Pos2, tolerance: f32) {
+        let handle = self.hit(ptr, tolerance);
+        self.dragging = Some(handle);
+        self.drag_origin = ptr;
+        self.rect_origin = self.rect.unwrap_or(Rect::from_min_max(ptr, ptr));
+    }
+
+    pub fn end_drag(&mut self) {
+        self.dragging = None;
+        //Discard rectangles too small to be meaningful (e.g. a simple click)
+        match self.rect {
+            Some(r) if r.width() < 1. || r.height() < 1. => self.rect = None,
+            _ => {}
+        }
+    }
+
+    /// Update the rectangle following the pointer, keeping it inside an image of the given size
+    pub fn drag(&mut self, ptr: Pos2, width: u32, height: u32) {
+        let bounds = Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32));
+        let ptr = bounds.clamp(ptr);
+        let r = self.rect_origin;
+        let new_rect = match self.dragging {
+            None => return,
+            Some(CropHandle::Move) => {
+                let mut delta = ptr - self.drag_origin;
+                delta.x = delta.x.clamp(bounds.left() - r.left(), bounds.right() - r.right());
+                delta.y = delta.y.clamp(bounds.top() - r.top(), bounds.bottom() - r.bottom());
+                r.translate(delta)
+            }
+            Some(CropHandle::New) => self.fit_ratio(self.drag_origin, ptr, true, true, bounds),
+            Some(CropHandle::TopLeft) => self.fit_ratio(r.right_bottom(), ptr, true, true, bounds),
+            Some(CropHandle::TopRight) => self.fit_ratio(r.left_bottom(), ptr, true, true, bounds),
+            Some(CropHandle::BottomRight) => self.fit_ratio(r.left_top(), ptr, true, true, bounds),
+            Some(CropHandle::BottomLeft) => self.fit_ratio(r.right_top(), ptr, true, true, bounds),
+            Some(CropHandle::Top) => self.fit_ratio(r.left_bottom(), Pos2::new(r.right(), ptr.y), false, true, bounds),
+            Some(CropHandle::Bottom) => self.fit_ratio(r.left_top(), Pos2::new(r.right(), ptr.y), false, true, bounds),
+            Some(CropHandle::Left) => self.fit_ratio(r.right_top(), Pos2::new(ptr.x, r.bottom()), true, false, bounds),
+            Some(CropHandle::Right) => self.fit_ratio(r.left_top(), Pos2::new(ptr.x, r.bottom()), true, false, bounds),
+        };
+        self.rect = Some(new_rect);
+    }
+
+    /// Build the rectangle between the fixed [anchor] and [ptr], applying the aspect ratio if one is selected.
+    /// [x_driven]/[y_driven] tell which side was moved by the user, the other one is adapted to the ratio
+    fn fit_ratio(&self, anchor: Pos2, ptr: Pos2, x_driven: bool, y_driven: bool, bounds: Rect) -> Rect {
+        let mut size = ptr - anchor;
+        if let Some(ratio) = self.ratio() {
+            let (w, h) = (size.x.abs(), size.y.abs());
+            //When both sides are free, the larger movement decides
+            let (w, h) = if x_driven && (!y_driven || w >= h * ratio) { (w, w / ratio) } else { (h * ratio, h) };
+            let sign_x = if size.x < 0. { -1. } else { 1. };
+            let sign_y = if size.y < 0. { -1. } else { 1. };
+            //Shrink proportionally if the rectangle would exceed the image
+            let max_w = if sign_x < 0. { anchor.x - bounds.left() } else { bounds.right() - anchor.x };
+            let max_h = if sign_y < 0. { anchor.y - bounds.top() } else { bounds.bottom() - anchor.y };
+            let scale = (max_w / w.max(f32::EPSILON)).min(max_h / h.max(f32::EPSILON)).min(1.);
+            size = Vec2::new(w * scale * sign_x, h * scale * sign_y);
+        }
+        Rect::from_two_pos(anchor, anchor + size)
+    }
+
+    /// Apply the current aspect ratio to the existing rectangle, keeping its top left corner
+    pub fn apply_ratio(&mut self, width: u32, height: u32) {
+        if let (Some(r), Some(_)) = (self.rect, self.ratio()) {
+            let bounds = Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32));
+            self.rect = Some(self.fit_ratio(r.left_top(), r.right_bottom(), true, false, bounds));
+        }
+    }
+
+    /// Integer (x, y, width, height) of the rectangle, clamped inside an image of the given size
+    pub fn get_crop_area(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let r = self.rect?;
+        let x = (r.left().round().max(0.) as u32).min(width.saturating_sub(1));
+        let y = (r.top().round().max(0.) as u32).min(height.saturating_sub(1));
+        let w = max(1, (r.width().round() as u32).min(width - x));
+        let h = max(1, (r.height().round() as u32).min(height - y));
+        Some((x, y, w, h))
+    }
+}
+
+/// Space between the border of a callout and its text
+pub const CALLOUT_PADDING: f32 = 8.;
+/// Size of the box created by a click, without dragging
+const CALLOUT_DEFAULT_SIZE: Vec2 = Vec2::new(160., 60.);
+
+/// Part of a callout moved by the pointer
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum CalloutDrag {
+    Box,
+    Tail,
+    //A new box is being drawn
+    New,
+}
+
+/// Speech bubble being edited: a rounded box with text and a tail pointing to a spot of the image, expressed in image pixels
+pub struct CalloutManager {
+    //Box as drawn by the user, it grows if the text doesn't fit
+    pub rect: Option<Rect>,
+    pub tail: Pos2,
+    pub buffer: TextBuffer,
+    //Without a fill color of its own the box is black or white, whichever stands out against the text
+    pub fill_color: [u8; 4],
+    pub contrasting_fill: bool,
+    pub dragging: Option<CalloutDrag>,
+    //Image without the callout, it is drawn again on it at every change and stacked with it once completed
+    pub original_img: Image,
+    //Area of the image covered by the callout in the last preview
+    pub preview_area: Option<Rect>,
+    drag_origin: Pos2,
+    rect_origin: Rect,
+    tail_origin: Pos2,
+}
+
+impl CalloutManager {
+    /// Color filling the box of a callout with border and text of the given [color]
+    pub fn fill(&self, color: [u8; 4]) -> [u8; 4] {
+        if !self.contrasting_fill {
+            return self.fill_color;
+        }
+        let luminance = 0.299 * color[0] as f32 + 0.587 * color[1] as f32 + 0.114 * color[2] as f32;
+        if luminance > 128. { [0, 0, 0, 255] } else { [255, 255, 255, 255] }
+    }
+
+    pub fn new() -> Self {
+        CalloutManager {
+            rect: None,
+            tail: Pos2::default(),
+            buffer: TextBuffer::new(),
+            fill_color: [255, 255, 255, 255],
+            contrasting_fill: true,
+            dragging: None,
+            original_img: Image::new(DynamicImage::default(), 0),
+            preview_area: None,
+            drag_origin: Pos2::default(),
+            rect_origin: Rect::NOTHING,
+            tail_origin: Pos2::default(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.rect = None;
+        self.dragging = None;
+        self.preview_area = None;
+        self.buffer.clear();
+    }
+
+    /// Start a new callout at [ptr] on top of [img], with a box of the default size and the tail below it
+    pub fn create(&mut self, ptr: Pos2, img: Image) {
+        let rect = Rect::from_min_size(ptr, CALLOUT_DEFAULT_SIZE);
+        self.rect = Some(rect);
+        self.tail = Pos2::new(rect.left() + rect.width() / 4., rect.bottom() + rect.height() * 0.75);
+        self.buffer.clear();
+        self.original_img = img;
+    }
+
+    /// Part of the callout under [ptr], given the current box (see [PaintState::callout_box])
+    pub fn hit(&self, ptr: Pos2, bubble: Rect, tolerance: f32) -> CalloutDrag {
+        if self.rect.is_some() && ptr.distance(self.tail) <= tolerance {
+            CalloutDrag::Tail
+        } else if self.rect.is_some() && bubble.contains(ptr) {
+            CalloutDrag::Box
+        } else {
+            CalloutDrag::New
+        }
+    }
+
+    pub fn start_drag(&mut self, ptr: Pos2, target: CalloutDrag) {
+        self.dragging = Some(target);
+        self.drag_origin = ptr;
+        self.rect_origin = self.rect.unwrap_or(Rect::from_min_size(ptr, Vec2::ZERO));
+        self.tail_origin = self.tail;
+    }
+
+    /// Move the part of the callout being dragged. Moving the box leaves the tail pointing to the same spot
+    pub fn drag(&mut self, ptr: Pos2) {
+        match self.dragging {
+            Some(CalloutDrag::Box) => self.rect = Some(self.rect_origin.translate(ptr - self.drag_origin)),
+            Some(CalloutDrag::Tail) => self.tail = self.tail_origin + (ptr - self.drag_origin),
+            Some(CalloutDrag::New) => {
+                let rect = Rect::from_two_pos(self.drag_origin, ptr);
+                self.rect = Some(rect);
+                self.tail = Pos2::new(rect.left() + rect.width() / 4., rect.bottom() + rect.height().max(20.) * 0.75);
+            }
+            None => {}
+        }
+    }
+
+    pub fn end_drag(&mut self) {
+        //A tiny box is most likely a click with a small movement
+        match (self.dragging, self.rect) {
+            (Some(CalloutDrag::New), Some(rect)) if rect.width() < 20. || rect.height() < 20. => {
+                let img = self.original_img.clone();
+                self.create(rect.min, img);
+            }
+            _ => {}
+        }
+        self.dragging = None;
+    }
+}
+
+/// Number of colors kept in the recent colors strip
+const MAX_RECENT_COLORS: usize = 12;
+
+/// Colors used recently and state of the eyedropper
+pub struct ColorManager {
+    pub recent: Vec<[u8; 4]>,
+    //Side of the square of pixels averaged by the eyedropper
+    pub sample_size: u32,
+    //Tool to go back to after picking a color
+    pub previous_tool: Tool,
+    pub selected_palette: usize,
+    pub new_palette_name: String,
+}
+
+impl ColorManager {
+    pub fn new() -> Self {
+        ColorManager {
+            recent: Vec::new(),
+            sample_size: 1,
+            previous_tool: Tool::None,
+            selected_palette: 0,
+            new_palette_name: String::new(),
+        }
+    }
+
+    /// Put [color] first in the recent colors, without duplicates
+    pub fn add_recent(&mut self, color: [u8; 4]) {
+        self.recent.retain(|c| *c != color);
+        self.recent.insert(0, color);
+        self.recent.truncate(MAX_RECENT_COLORS);
+    }
+}
+
+/// Color as #RRGGBB, with the alpha appended only if not opaque
+pub fn color_to_hex(color: [u8; 4]) -> String {
+    if color[3] == 255 {
+        format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", color[0], color[1], color[2], color[3])
+    }
+}
+
+pub fn color_to_rgb(color: [u8; 4]) -> String {
+    if color[3] == 255 {
+        format!("rgb({}, {}, {})", color[0], color[1], color[2])
+    } else {
+        format!("rgba({}, {}, {}, {:.2})", color[0], color[1], color[2], color[3] as f32 / 255.)
+    }
+}
+
+pub fn color_to_hsl(color: [u8; 4]) -> String {
+    let [r, g, b] = [color[0], color[1], color[2]].map(|c| c as f32 / 255.);
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let l = (max + min) / 2.;
+    let d = max - min;
+    let (h, s) = if d == 0. {
+        (0., 0.)
+    } else {
+        let s = d / (1. - (2. * l - 1.).abs());
+        let h = if max == r {
+            60. * ((g - b) / d).rem_euclid(6.)
+        } else if max == g {
+            60. * ((b - r) / d + 2.)
+        } else {
+            60. * ((r - g) / d + 4.)
+        };
+        (h, s)
+    };
+    format!("hsl({:.0}, {:.0}%, {:.0}%)", h, s * 100., l * 100.)
+}
+
+/// Relative luminance of [color], as defined by WCAG
+pub fn relative_luminance(color: [u8; 4]) -> f32 {
+    let [r, g, b] = [color[0], color[1], color[2]].map(|c| {
+        let c = c as f32 / 255.;
+        if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    });
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// WCAG contrast ratio between two colors, from 1 to 21
+pub fn contrast_ratio(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let (la, lb) = (relative_luminance(a), relative_luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Best WCAG level met by a contrast [ratio], for normal text
+pub fn wcag_level(ratio: f32) -> String {
+    if ratio >= 7. {
+        "AAA".to_string()
+    } else if ratio >= 4.5 {
+        "AA".to_string()
+    } else if ratio >= 3. {
+        "AA large text".to_string()
+    } else {
+        "Fail".to_string()
+    }
+}
+
+/// Distance, in screen points, within which the pointer snaps to a guide
+pub const GUIDE_TOLERANCE: f32 = 6.;
+
+/// Settings of the snapping of shapes to a pixel grid and to guides
+pub struct SnapSettings {
+    pub grid: bool,
+    pub grid_size: u32,
+    //Snap to the edges and centers of other annotations and to the edges detected in the image
+    pub guides: bool,
+}
+
+impl SnapSettings {
+    pub fn new() -> Self {
+        SnapSettings {
+            grid: false,
+            grid_size: 10,
+            guides: true,
+        }
+    }
+}
+
+/// Vertical ([xs]) and horizontal ([ys]) lines, in image pixels, the pointer can snap to
+#[derive(Clone, Default)]
+pub struct Guides {
+    pub xs: Vec<f32>,
+    pub ys: Vec<f32>,
+}
+
+impl Guides {
+    /// Guides of an image: sides and centers of its annotations, plus the long edges found in its content
+    pub fn from_image(img: &Image) -> Self {
+        let (edge_xs, edge_ys) = find_ui_edges(&img.image);
+        let mut guides = Guides {
+            xs: edge_xs.iter().map(|&x| x as f32).collect(),
+            ys: edge_ys.iter().map(|&y| y as f32).collect(),
+        };
+        for bounds in img.get_annotations().iter().map(|annotation| annotation.bounds) {
+            guides.xs.extend([bounds.left(), bounds.center().x, bounds.right()]);
+            guides.ys.extend([bounds.top(), bounds.center().y, bounds.bottom()]);
+        }
+        guides
+    }
+
+    /// Guide nearest to [value] among [lines], if closer than [tolerance]
+    fn nearest(lines: &[f32], value: f32, tolerance: f32) -> Option<f32> {
+        lines.iter()
+            .filter(|&&line| (line - value).abs() <= tolerance)
+            .min_by(|a, b| (*a - value).abs().total_cmp(&(*b - value).abs()))
+            .copied()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ArrowHead {
+    //No head, the tool draws plain lines
+    None,
+    Open,
+    Filled,
+}
+
+impl Display for ArrowHead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ArrowHead::None => "Line",
+            ArrowHead::Open => "Open",
+            ArrowHead::Filled => "Filled",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum StrokePattern {
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+impl StrokePattern {
+    /// Lengths of the drawn and skipped parts of the pattern, for a stroke of thickness [t]
+    pub fn dash(&self, t: usize) -> Option<(f32, f32)> {
+        let t = t as f32;
+        match self {
+            StrokePattern::Solid => None,
+            StrokePattern::Dashed => Some((4. * t + 8., 3. * t + 6.)),
+            StrokePattern::Dotted => Some((0., 3. * t + 4.)),
+        }
+    }
+}
+
+impl Display for StrokePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            StrokePattern::Solid => "Solid",
+            StrokePattern::Dashed => "Dashed",
+            StrokePattern::Dotted => "Dotted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Minimum distance, in image pixels, between two samples of a freehand stroke
+const MIN_STROKE_STEP: f32 = 1.5;
+/// Speed, in image pixels per second, at which the pen gets noticeably thinner
+const PEN_THINNING_SPEED: f32 = 1500.;
+
+/// Sample of a freehand stroke, with the radius of the pen there
+#[derive(Clone, Copy)]
+pub struct StrokePoint {
+    pub pos: Pos2,
+    pub radius: f32,
+}
+
+/// Options of the freehand pen
+#[derive(Clone, PartialEq)]
+pub struct PenStyle {
+    //Join the samples with a curve instead of straight lines
+    pub smoothing: bool,
+    //Make the stroke thinner when moving fast
+    pub speed_width: bool,
+}
+
+impl PenStyle {
+    pub fn new() -> Self {
+        PenStyle { smoothing: true, speed_width: false }
+    }
+}
+
+/// What the eraser removes: the pixels under the brush, or the whole annotations it touches
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum EraserMode {
+    Pixels,
+    Objects,
+}
+
+impl Display for EraserMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EraserMode::Pixels => "Pixels",
+            EraserMode::Objects => "Objects",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Appearance of the lines drawn by the arrow tool
+#[derive(Clone, PartialEq)]
+pub struct ArrowStyle {
+    pub head: ArrowHead,
+    //Draw a head also at the start of the line
+    pub double: bool,
+    //How much the line is bent, relative to its length: 0 is straight, the sign tells the side
+    pub bend: f32,
+    pub pattern: StrokePattern,
+    //Multiplier of the default size of the heads, which depends on the thickness
+    pub head_size: f32,
+}
+
+impl ArrowStyle {
+    pub fn new() -> Self {
+        ArrowStyle {
+            head: ArrowHead::Open,
+            double: false,
+            bend: 0.,
+            pattern: StrokePattern::Solid,
+            head_size: 1.,
+        }
+    }
+}
+
+/// Shape of an arrow: the points of its line and the tip and direction of each of its heads, [size] pixels long
+pub struct ArrowGeometry {
+    pub points: Vec<(f32, f32)>,
+    pub heads: Vec<(Pos2, Vec2)>,
+    pub size: f32,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum InsetShape {
+    Circle,
+    Rectangle,
+}
+
+impl Display for InsetShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InsetShape::Circle => "Circle",
+            InsetShape::Rectangle => "Rectangle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Part of a magnifier moved by the pointer
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum MagnifierDrag {
+    Source,
+    Inset,
+    //A new source region is being drawn
+    New,
+}
+
+/// Magnifier being edited: a small [source] region of the image shown enlarged in an inset centered in [inset_center], in image pixels
+pub struct MagnifierManager {
+    pub source: Option<Rect>,
+    pub inset_center: Pos2,
+    pub shape: InsetShape,
+    pub factor: f32,
+    pub dragging: Option<MagnifierDrag>,
+    //Image without the magnifier, used both as background and to sample the enlarged region. Stacked with it once confirmed
+    pub original_img: Image,
+    //Area of the image covered by the magnifier in the last preview
+    pub preview_area: Option<Rect>,
+    drag_origin: Pos2,
+    source_origin: Rect,
+    center_origin: Pos2,
+}
+
+impl MagnifierManager {
+    pub fn new() -> Self {
+        MagnifierManager {
+            source: None,
+            inset_center: Pos2::default(),
+            shape: InsetShape::Circle,
+            factor: 3.,
+            dragging: None,
+            original_img: Image::new(DynamicImage::default(), 0),
+            preview_area: None,
+            drag_origin: Pos2::default(),
+            source_origin: Rect::NOTHING,
+            center_origin: Pos2::default(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.source = None;
+        self.dragging = None;
+        self.preview_area = None;
+    }
+
+    /// Start a new magnifier on top of [img], the source region is drawn by dragging from [ptr]
+    pub fn create(&mut self, ptr: Pos2, img: Image) {
+        self.source = Some(Rect::from_min_size(ptr, Vec2::ZERO));
+        self.original_img = img;
+        self.start_drag(ptr, MagnifierDrag::New);
+    }
+
+    /// Half size of the inset, which is the source region enlarged by [factor]. A circle encloses the whole source region
+    pub fn inset_half_size(&self) -> Vec2 {
+        let source = self.source.unwrap_or(Rect::NOTHING);
+        match self.shape {
+            InsetShape::Circle => Vec2::splat(source.width().max(source.height()) / 2. * self.factor),
+            InsetShape::Rectangle => source.size() / 2. * self.factor,
+        }
+    }
+
+    /// Area covered by the source region, the inset and the line between them, with borders [thickness] wide
+    pub fn area(&self, thickness: usize) -> Rect {
+        let source = self.source.unwrap_or(Rect::NOTHING);
+        //A circle around a region that is not square goes out of it
+        let outline = Rect::from_center_size(source.center(), Vec2::splat(source.width().max(source.height())));
+        let inset = Rect::from_center_size(self.inset_center, self.inset_half_size() * 2.);
+        source.union(outline).union(inset).expand(thickness as f32 + 2.)
+    }
+
+    /// Part of the magnifier under [ptr]. The inset is checked first since it is drawn on top
+    pub fn hit(&self, ptr: Pos2) -> MagnifierDrag {
+        let source = match self.source {
+            Some(source) => source,
+            None => return MagnifierDrag::New,
+        };
+        let half = self.inset_half_size();
+        let inside_inset = match self.shape {
+            InsetShape::Circle => ptr.distance(self.inset_center) <= half.x,
+            InsetShape::Rectangle => Rect::from_center_size(self.inset_center, half * 2.).contains(ptr),
+        };
+        if inside_inset {
+            MagnifierDrag::Inset
+        } else if source.contains(ptr) {
+            MagnifierDrag::Source
+        } else {
+            MagnifierDrag::New
+        }
+    }
+
+    pub fn start_drag(&mut self, ptr: Pos2, target: MagnifierDrag) {
+        self.dragging = Some(target);
+        self.drag_origin = ptr;
+        self.source_origin = self.source.unwrap_or(Rect::from_min_size(ptr, Vec2::ZERO));
+        self.center_origin = self.inset_center;
+    }
+
+    pub fn drag(&mut self, ptr: Pos2) {
+        match self.dragging {
+            Some(MagnifierDrag::Source) => self.source = Some(self.source_origin.translate(ptr - self.drag_origin)),
+            Some(MagnifierDrag::Inset) => self.inset_center = self.center_origin + (ptr - self.drag_origin),
+            Some(MagnifierDrag::New) => self.source = Some(Rect::from_two_pos(self.drag_origin, ptr)),
+            None => {}
+        }
+    }
+
+    /// Complete the drag. After drawing a new source region, the inset is placed next to it, on the side with more room
+    pub fn end_drag(&mut self, width: u32, height: u32) {
+        if let (Some(MagnifierDrag::New), Some(source)) = (self.dragging, self.source) {
+            //A click without dragging magnifies a small square around the pointer
+            let source = if source.width() < 4. || source.height() < 4. { Rect::from_center_size(source.center(), Vec2::splat(40.)) } else { source };
+            self.source = Some(source);
+            let half = self.inset_half_size();
+            let gap = half.x + source.width() / 2. + 20.;
+            let x = if source.center().x + gap + half.x <= width as f32 || source.center().x < width as f32 / 2. { source.center().x + gap } else { source.center().x - gap };
+            let y = source.center().y.clamp(half.y, (height as f32 - half.y).max(half.y));
+            self.inset_center = Pos2::new(x, y);
+        }
+        self.dragging = None;
+    }
+}
+
+/// Distance between two points of the image and the colors sampled to check their contrast
+pub struct MeasureManager {
+    pub start: Option<Pos2>,
+    pub end: Pos2,
+    pub dragging: bool,
+    pub rulers: bool,
+    //The two colors compared, with where they were sampled
+    pub samples: [Option<([u8; 4], Pos2)>; 2],
+    //Which sample the next click picks, instead of measuring
+    pub picking: Option<usize>,
+}
+
+impl MeasureManager {
+    pub fn new() -> Self {
+        MeasureManager {
+            start: None,
+            end: Pos2::default(),
+            dragging: false,
+            rulers: true,
+            samples: [None, None],
+            picking: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.dragging = false;
+        self.picking = None;
+    }
+
+    /// Distance, angle from the horizontal line, counterclockwise, and horizontal and vertical distances
+    pub fn label(&self) -> Option<String> {
+        let delta = self.end - self.start?;
+        let angle = (-delta.y).atan2(delta.x).to_degrees();
+        Some(format!("{:.1} px  {:.1}°  ({:.1} × {:.1})", delta.length(), angle, delta.x.abs(), delta.y.abs()))
+    }
+
+    pub fn contrast(&self) -> Option<f32> {
+        match self.samples {
+            [Some((a, _)), Some((b, _))] => Some(contrast_ratio(a, b)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SpotShape {
+    Rectangle,
+    Ellipse,
+}
+
+impl Display for SpotShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SpotShape::Rectangle => "Rectangle",
+            SpotShape::Ellipse => "Ellipse",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Areas of the image left bright while the rest is dimmed, all drawn as a single layer until confirmed
+pub struct SpotlightManager {
+    pub areas: Vec<(Rect, SpotShape)>,
+    pub shape: SpotShape,
+    pub dim: f32,
+    pub desaturate: f32,
+    pub feather: f32,
+    //Index of the area being drawn or moved
+    pub dragging: Option<usize>,
+    //Image without the spotlight, and whether the spotlight is being previewed on it
+    pub original_img: Image,
+    pub active: bool,
+    //Area of the image where the spotlight changed since it was last drawn
+    pub changed_area: Option<Rect>,
+    moving: bool,
+    drag_origin: Pos2,
+    rect_origin: Rect,
+}
+
+impl SpotlightManager {
+    pub fn new() -> Self {
+        SpotlightManager {
+            areas: Vec::new(),
+            shape: SpotShape::Rectangle,
+            dim: 0.6,
+            desaturate: 0.5,
+            feather: 0.,
+            dragging: None,
+            original_img: Image::new(DynamicImage::default(), 0),
+            active: false,
+            changed_area: None,
+            moving: false,
+            drag_origin: Pos2::default(),
+            rect_origin: Rect::NOTHING,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.areas.clear();
+        self.dragging = None;
+        self.active = false;
+        self.changed_area = None;
+    }
+
+    fn mark_changed(&mut self, area: Rect) {
+        self.changed_area = Some(match self.changed_area {
+            Some(changed) => changed.union(area),
+            None => area,
+        });
+    }
+
+    /// Index of the topmost area under [ptr]
+    pub fn hit(&self, ptr: Pos2) -> Option<usize> {
+        self.areas.iter().rposition(|(rect, _)| rect.contains(ptr))
+    }
+
+    /// Move the area under [ptr], or draw a new one if there is none
+    pub fn start_drag(&mut self, ptr: Pos2) {
+        let index = match self.hit(ptr) {
+            Some(index) => {
+                self.moving = true;
+                index
+            }
+            None => {
+                self.moving = false;
+                self.areas.push((Rect::from_min_size(ptr, Vec2::ZERO), self.shape));
+                self.areas.len() - 1
+            }
+        };
+        self.dragging = Some(index);
+        self.drag_origin = ptr;
+        self.rect_origin = self.areas[index].0;
+    }
+
+    pub fn drag(&mut self, ptr: Pos2) {
+        let index = match self.dragging {
+            Some(index) => index,
+            None => return,
+        };
+        let old = self.areas[index].0;
+        self.areas[index].0 = if self.moving { self.rect_origin.translate(ptr - self.drag_origin) } else { Rect::from_two_pos(self.drag_origin, ptr) };
+        self.mark_changed(old.union(self.areas[index].0));
+    }
+
+    /// Complete the drag, dropping an area too small to be seen
+    pub fn end_drag(&mut self) {
+        match self.dragging {
+            Some(index) if self.areas[index].0.width() < 3. || self.areas[index].0.height() < 3. => {
+                self.remove(index);
+            }
+            _ => {}
+        }
+        self.dragging = None;
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        let (rect, _) = self.areas.remove(index);
+        self.mark_changed(rect);
+    }
+
+    /// Part of the image where the spotlight has to be drawn again, including the soft edges
+    pub fn take_changed_area(&mut self) -> Option<Rect> {
+        self.changed_area.take().map(|area| area.expand(self.feather + 1.))
+    }
+
+    /// Area covering all the spots
+    pub fn bounds(&self) -> Rect {
+        self.areas.iter().fold(Rect::NOTHING, |bounds, (rect, _)| bounds.union(*rect))
+    }
+}
+
+/// Part of the inserted image being dragged
+#[derive(PartialEq, Clone, Copy)]
+pub enum InsertDrag {
+    Move,
+    Resize,
+}
+
+/// Image, sticker or emoji being placed on the screenshot, it can be moved and scaled until it is confirmed
+pub struct InsertManager {
+    pub content: Option<DynamicImage>,
+    pub rect: Option<Rect>,
+    pub dragging: Option<InsertDrag>,
+    pub emoji: String,
+    //Why the last content could not be loaded
+    pub error: Option<String>,
+    //Image without the inserted one, redrawn every time it is moved and stacked with it once confirmed
+    pub original_img: Image,
+    //Area of the image covered by the inserted image in the last preview
+    pub preview_area: Option<Rect>,
+    //Content resized to [rect], kept while the size does not change
+    scaled: Option<DynamicImage>,
+    drag_origin: Pos2,
+    rect_origin: Rect,
+}
+
+impl InsertManager {
+    pub fn new() -> Self {
+        InsertManager {
+            content: None,
+            rect: None,
+            dragging: None,
+            emoji: String::new(),
+            error: None,
+            original_img: Image::new(DynamicImage::default(), 0),
+            preview_area: None,
+            scaled: None,
+            drag_origin: Pos2::default(),
+            rect_origin: Rect::NOTHING,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.content = None;
+        self.rect = None;
+        self.dragging = None;
+        self.scaled = None;
+        self.preview_area = None;
+    }
+
+    /// Place [content] at the center of [img], scaled down to fit in half of it
+    pub fn place(&mut self, content: DynamicImage, img: Image) {
+        let (width, height) = (img.get_width() as f32, img.get_height() as f32);
+        let size = Vec2::new(content.width().max(1) as f32, content.height().max(1) as f32);
+        let scale = (width / 2. / size.x).min(height / 2. / size.y).min(1.);
+        self.rect = Some(Rect::from_center_size(Pos2::new(width / 2., height / 2.), size * scale));
+        self.content = Some(content);
+        self.scaled = None;
+        self.dragging = None;
+        self.preview_area = None;
+        self.original_img = img;
+    }
+
+    /// Part of the inserted image under [ptr]: the resize handle in the bottom right corner, within [tolerance], or the image itself
+    pub fn hit(&self, ptr: Pos2, tolerance: f32) -> Option<InsertDrag> {
+        let rect = self.rect?;
+        if ptr.distance(rect.max) <= tolerance {
+            Some(InsertDrag::Resize)
+        } else if rect.contains(ptr) {
+            Some(InsertDrag::Move)
+        } else {
+            None
+        }
+    }
+
+    pub fn start_drag(&mut self, ptr: Pos2, target: InsertDrag) {
+        self.dragging = Some(target);
+        self.drag_origin = ptr;
+        self.rect_origin = self.rect.unwrap_or(Rect::from_min_size(ptr, Vec2::ZERO));
+    }
+
+    pub fn drag(&mut self, ptr: Pos2) {
+        let origin = self.rect_origin;
+        match self.dragging {
+            Some(InsertDrag::Move) => self.rect = Some(origin.translate(ptr - self.drag_origin)),
+            //The aspect ratio is kept, with the top left corner still
+            Some(InsertDrag::Resize) => {
+                let scale = ((ptr.x - origin.min.x) / origin.width()).max((ptr.y - origin.min.y) / origin.height());
+                let min_scale = 4. / origin.width().min(origin.height());
+                self.rect = Some(Rect::from_min_size(origin.min, origin.size() * scale.max(min_scale)));
+            }
+            None => {}
+        }
+    }
+
+    pub fn end_drag(&mut self) {
+        self.dragging = None;
+    }
+
+    /// Content resized to the area it is placed in
+    pub fn scaled_content(&mut self) -> Option<&DynamicImage> {
+        let rect = self.rect?;
+        let (width, height) = (rect.width().round().max(1.) as u32, rect.height().round().max(1.) as u32);
+        let outdated = match &self.scaled {
+            Some(scaled) => scaled.width() != width || scaled.height() != height,
+            None => true,
+        };
+        if outdated {
+            self.scaled = Some(self.content.as_ref()?.resize_exact(width, height, FilterType::Triangle));
+        }
+        self.scaled.as_ref()
+    }
+}
+
+/// Geometric transformation of the whole image
+#[derive(PartialEq, Clone, Copy)]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Resize(u32, u32, FilterType),
+}
+
+impl Transform {
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            Transform::Rotate90 => img.rotate90(),
+            Transform::Rotate180 => img.rotate180(),
+            Transform::Rotate270 => img.rotate270(),
+            Transform::FlipHorizontal => img.fliph(),
+            Transform::FlipVertical => img.flipv(),
+            Transform::Resize(width, height, filter) => img.resize_exact(*width, *height, *filter),
+        }
+    }
+}
+
+/// Largest side of a resized image, to keep its buffer allocatable
+pub const MAX_RESIZE_SIDE: u32 = 20000;
+
+/// Settings of the resize window, either by percentage or by target dimensions
+pub struct ResizeManager {
+    pub by_percentage: bool,
+    pub percentage: u32,
+    pub width: u32,
+    pub height: u32,
+    pub keep_aspect: bool,
+    pub filter: FilterType,
+}
+
+impl ResizeManager {
+    pub fn new() -> Self {
+        ResizeManager {
+            by_percentage: true,
+            percentage: 100,
+            width: 0,
+            height: 0,
+            keep_aspect: true,
+            filter: FilterType::Lanczos3,
+        }
+    }
+
+    /// Start from the dimensions of the image about to be resized
+    pub fn init(&mut self, width: u32, height: u32) {
+        self.percentage = 100;
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Target dimensions for an image of the given size
+    pub fn target_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.by_percentage {
+            //Both sides are scaled down together if the longer one would be larger than the limit
+            let longest = max(1, max(width, height)) as u64;
+            let scaled = (longest * self.percentage as u64 / 100).min(MAX_RESIZE_SIDE as u64);
+            (max(1, (width as u64 * scaled / longest) as u32), max(1, (height as u64 * scaled / longest) as u32))
+        } else {
+            (max(1, self.width), max(1, self.height))
+        }
+    }
+
+    /// Keep the target dimensions proportional to the image after one of them was edited
+    pub fn update_aspect(&mut self, width: u32, height: u32, width_changed: bool) {
+        if !self.keep_aspect || width == 0 || height == 0 {
+            return;
+        }
+        if width_changed {
+            self.height = max(1, (self.width as u64 * height as u64 / width as u64) as u32);
+        } else {
+            self.width = max(1, (self.height as u64 * width as u64 / height as u64) as u32);
+        }
+    }
+}
+
+pub fn filter_name(filter: FilterType) -> String {
+    match filter {
+        FilterType::Nearest => "Nearest".to_string(),
+        FilterType::Triangle => "Bilinear".to_string(),
+        FilterType::CatmullRom => "Bicubic".to_string(),
+        FilterType::Gaussian => "Gaussian".to_string(),
+        FilterType::Lanczos3 => "Lanczos".to_string(),
+    }
+}
 
 pub struct PaintState {
     pub curr_tool: Tool,
     pub curr_color: [u8; 4],
     pub curr_thickness: usize,
     pub text_info: TextManager,
+    pub crop_info: CropManager,
+    pub callout_info: CalloutManager,
+    pub magnifier_info: MagnifierManager,
+    pub insert_info: InsertManager,
+    pub measure_info: MeasureManager,
+    pub spotlight_info: SpotlightManager,
+    pub arrow_style: ArrowStyle,
+    pub colors: ColorManager,
+    pub snap: SnapSettings,
+    pub guides: Guides,
+    //Guides the pointer is currently snapped to, shown while drawing
+    pub active_guides: (Option<f32>, Option<f32>),
+    //Shift: squares, circles and 45° lines. Alt: the shape grows from the point where the drag started
+    pub constrain: bool,
+    pub from_center: bool,
+    //Area covered by the freehand stroke being drawn
+    pub stroke_bounds: Option<Rect>,
+    //Coverage of the pen or highlighter stroke being drawn, so that it is composited once as a whole
+    pub stroke_mask: Option<GrayImage>,
+    pub pen: PenStyle,
+    //Samples of the freehand stroke being drawn, and how many of its segments are already in [stroke_mask]
+    pub stroke: Vec<StrokePoint>,
+    stroke_drawn: usize,
+    last_sample_time: f64,
+    //What the pen and the highlighter draw over, or what the eraser restores, taken once when the stroke starts
+    pub stroke_original: Option<DynamicImage>,
+    //Area of the image changed by the tool since it was last taken, to show again only that part
+    pub changed_area: Option<Rect>,
+    pub highlighter_opacity: f32,
+    pub eraser_mode: EraserMode,
     pub painting: bool,
     pub last_ptr: Pos2,
     pub curr_ptr: Pos2,
@@ -109,103 +1337,1182 @@ impl PaintState {
             curr_color: [255, 255, 255, 255],
             curr_thickness: 1,
             painting: false,
-            text_info: TextManager::new("Roboto-Light".to_string(), Pos2::default(), Image::new(DynamicImage::default(), 0)),
+            text_info: TextManager::new(DEFAULT_FONT.to_string(), default_font(), Pos2::default(), Image::new(DynamicImage::default(), 0)),
+            crop_info: CropManager::new(),
+            callout_info: CalloutManager::new(),
+            magnifier_info: MagnifierManager::new(),
+            insert_info: InsertManager::new(),
+            measure_info: MeasureManager::new(),
+            spotlight_info: SpotlightManager::new(),
+            arrow_style: ArrowStyle::new(),
+            colors: ColorManager::new(),
+            snap: SnapSettings::new(),
+            guides: Guides::default(),
+            active_guides: (None, None),
+            constrain: false,
+            from_center: false,
+            stroke_bounds: None,
+            stroke_mask: None,
+            pen: PenStyle::new(),
+            stroke: Vec::new(),
+            stroke_drawn: 0,
+            last_sample_time: 0.,
+            stroke_original: None,
+            changed_area: None,
+            highlighter_opacity: 0.4,
+            eraser_mode: EraserMode::Pixels,
             last_ptr: Pos2::default(),
             curr_ptr: Pos2::default(),
         }
     }
-    /// Reset the paint state to its default values, including the current tool and color
-    pub fn reset(&mut self) {
-        self.painting = false;
-        self.last_ptr = Pos2::default();
-        self.curr_ptr = Pos2::default();
-        self.curr_tool = Tool::None;
-        self.curr_color = [255, 255, 255, 255];
+    /// Reset the paint state to its default values, including the current tool and color
+    pub fn reset(&mut self) {
+        self.painting = false;
+        self.last_ptr = Pos2::default();
+        self.curr_ptr = Pos2::default();
+        self.curr_tool = Tool::None;
+        self.curr_color = [255, 255, 255, 255];
+        self.crop_info.reset();
+        self.callout_info.reset();
+        self.magnifier_info.reset();
+    }
+
+    /// Reset the paint state to its default values, excluding the current tool and color
+    pub fn soft_reset(&mut self) {
+        self.painting = false;
+        self.last_ptr = Pos2::default();
+        self.curr_ptr = Pos2::default();
+        self.active_guides = (None, None);
+        self.stroke_bounds = None;
+        self.stroke_mask = None;
+        self.stroke.clear();
+        self.stroke_drawn = 0;
+        self.stroke_original = None;
+    }
+
+    /// Whether a tool is editing the last stacked image in place, replacing it until it is confirmed
+    pub fn editing_in_place(&self) -> bool {
+        self.painting || self.text_info.dirty || self.callout_info.rect.is_some() || self.magnifier_info.source.is_some()
+            || self.spotlight_info.active || self.insert_info.rect.is_some()
+    }
+
+    /// Add the pointer position at [time], in seconds, to the freehand stroke.
+    /// With the speed width enabled the pen gets thinner when moving fast, like ink
+    pub fn push_stroke_point(&mut self, pos: Pos2, time: f64) {
+        let base = self.curr_thickness.max(1) as f32;
+        let radius = match self.stroke.last() {
+            //Samples too close add only noise to the curve
+            Some(last) if (pos - last.pos).length() < MIN_STROKE_STEP => return,
+            Some(last) if self.pen.speed_width => {
+                let speed = (pos - last.pos).length() / (time - self.last_sample_time).max(0.001) as f32;
+                let target = base * (1.25 - speed / PEN_THINNING_SPEED).clamp(0.35, 1.25);
+                //The width changes gradually, to avoid steps along the stroke
+                last.radius + (target - last.radius) * 0.35
+            }
+            _ => base,
+        };
+        self.stroke.push(StrokePoint { pos, radius });
+        self.last_sample_time = time;
+    }
+
+    /// Draw the segments of the freehand stroke not drawn yet on [img], which was [original_img] before the stroke.
+    /// The last segment depends on the next sample when smoothing, so it is drawn only once the stroke is [finished]
+    pub fn draw_pen_stroke(&mut self, img: &mut Image, original_img: &DynamicImage, finished: bool) {
+        let points = &self.stroke;
+        if points.is_empty() {
+            return;
+        }
+        let ready = if finished || !self.pen.smoothing { points.len() - 1 } else { points.len().saturating_sub(2) };
+        let (width, height) = (img.get_width(), img.get_height());
+        let mask = self.stroke_mask.get_or_insert_with(|| GrayImage::new(width, height));
+        let mut area: Option<(u32, u32, u32, u32)> = None;
+        let mut add = |start: (f32, f32), end: (f32, f32), r0: f32, r1: f32| {
+            let (x0, y0, x1, y1) = add_tapered_stroke_to_mask(mask, start, end, r0, r1);
+            area = Some(match area {
+                Some(a) => (a.0.min(x0), a.1.min(y0), a.2.max(x1), a.3.max(y1)),
+                None => (x0, y0, x1, y1),
+            });
+        };
+        //A click without moving leaves a dot
+        if points.len() == 1 && finished {
+            let p = (points[0].pos.x, points[0].pos.y);
+            add(p, p, points[0].radius, points[0].radius);
+        }
+        for i in self.stroke_drawn..ready {
+            let (p1, p2) = (points[i], points[i + 1]);
+            if !self.pen.smoothing {
+                add((p1.pos.x, p1.pos.y), (p2.pos.x, p2.pos.y), p1.radius, p2.radius);
+                continue;
+            }
+            let p0 = points[i.saturating_sub(1)].pos;
+            let p3 = points[(i + 2).min(points.len() - 1)].pos;
+            let curve = catmull_rom_points((p0.x, p0.y), (p1.pos.x, p1.pos.y), (p2.pos.x, p2.pos.y), (p3.x, p3.y));
+            let steps = (curve.len() - 1) as f32;
+            for (j, piece) in curve.windows(2).enumerate() {
+                let r0 = p1.radius + (p2.radius - p1.radius) * j as f32 / steps;
+                let r1 = p1.radius + (p2.radius - p1.radius) * (j + 1) as f32 / steps;
+                add(piece[0], piece[1], r0, r1);
+            }
+        }
+        self.stroke_drawn = self.stroke_drawn.max(ready);
+        if let Some(area) = area {
+            composite_stroke_mut(original_img, img.pixels_mut(), mask, area, self.curr_color);
+            self.mark_changed(Rect::from_min_max(Pos2::new(area.0 as f32, area.1 as f32), Pos2::new(area.2 as f32, area.3 as f32)));
+        }
+    }
+
+    fn mark_changed(&mut self, area: Rect) {
+        self.changed_area = Some(match self.changed_area {
+            Some(changed) => changed.union(area),
+            None => area,
+        });
+    }
+
+    /// Geometry of the current arrow, its line stopped inside filled heads
+    pub fn arrow_geometry(&self) -> ArrowGeometry {
+        let style = &self.arrow_style;
+        let (start, end) = self.shape_points();
+        //The control point of the curve is moved away from the middle of the line, perpendicularly to it
+        let control = start + (end - start) / 2. + (end - start).rot90() * style.bend / 2.;
+        let size = 10. * ((self.curr_thickness as f32) / 3. + 1.) * style.head_size;
+        let filled = style.head == ArrowHead::Filled;
+        let mut points = if style.bend == 0. {
+            vec![(start.x, start.y), (end.x, end.y)]
+        } else {
+            quadratic_bezier_points((start.x, start.y), (control.x, control.y), (end.x, end.y))
+        };
+        //Heads follow the direction of the curve at its ends
+        let end_direction = if style.bend == 0. { end - start } else { end - control };
+        let start_direction = if style.bend == 0. { start - end } else { start - control };
+        //Stop the line inside filled heads, so that its round end doesn't stick out of the tip
+        if filled && end_direction.length() > size {
+            let last = points.len() - 1;
+            let back = end - end_direction.normalized() * size / 2.;
+            points[last] = (back.x, back.y);
+            if style.double {
+                let front = start - start_direction.normalized() * size / 2.;
+                points[0] = (front.x, front.y);
+            }
+        }
+        let mut heads = Vec::new();
+        if style.head != ArrowHead::None && end_direction.length() > 0. {
+            heads.push((end, end_direction));
+            if style.double {
+                heads.push((start, start_direction));
+            }
+        }
+        ArrowGeometry { points, heads, size }
+    }
+
+    /// Area of the image covered by the current shape, lines included
+    pub fn shape_area(&self) -> Option<Rect> {
+        let t = self.curr_thickness as f32 + 2.;
+        match self.curr_tool {
+            Tool::Arrow => {
+                let ArrowGeometry { points, heads, size } = self.arrow_geometry();
+                let area = points.iter().fold(Rect::NOTHING, |area, p| area.union(Rect::from_min_max(Pos2::new(p.0, p.1), Pos2::new(p.0, p.1))));
+                Some(heads.iter().fold(area, |area, (tip, _)| area.union(Rect::from_center_size(*tip, Vec2::splat(size * 3.)))).expand(t))
+            }
+            _ => self.annotation_bounds().map(|bounds| bounds.expand(t)),
+        }
+    }
+
+    /// Move [ptr] to the nearest grid point and then to the nearest guide, if enabled. [tolerance] is in image pixels
+    pub fn snap_point(&mut self, ptr: Pos2, tolerance: f32) -> Pos2 {
+        let mut snapped = ptr;
+        if self.snap.grid && self.snap.grid_size > 1 {
+            let size = self.snap.grid_size as f32;
+            snapped = Pos2::new((ptr.x / size).round() * size, (ptr.y / size).round() * size);
+        }
+        self.active_guides = (None, None);
+        if self.snap.guides {
+            //Guides win over the grid, they are what the user is aiming at
+            if let Some(x) = Guides::nearest(&self.guides.xs, ptr.x, tolerance) {
+                snapped.x = x;
+                self.active_guides.0 = Some(x);
+            }
+            if let Some(y) = Guides::nearest(&self.guides.ys, ptr.y, tolerance) {
+                snapped.y = y;
+                self.active_guides.1 = Some(y);
+            }
+        }
+        snapped
+    }
+
+    /// Grow the bounds of the freehand stroke being drawn to include the current segment
+    pub fn extend_stroke_bounds(&mut self) {
+        let t = self.curr_thickness as f32;
+        let segment = Rect::from_two_pos(self.last_ptr, self.curr_ptr).expand(t);
+        self.stroke_bounds = Some(match self.stroke_bounds {
+            Some(bounds) => bounds.union(segment),
+            None => segment,
+        });
+    }
+
+    /// Start and end of the current shape, after applying the modifier constraints to [last_ptr] and [curr_ptr]
+    pub fn shape_points(&self) -> (Pos2, Pos2) {
+        let (start, mut end) = (self.last_ptr, self.curr_ptr);
+        //Circles are already perfect and drawn from their center
+        if !self.curr_tool.is_shape() || self.curr_tool == Tool::HollowCircle || self.curr_tool == Tool::FilledCircle {
+            return (start, end);
+        }
+        let delta = end - start;
+        if self.constrain {
+            end = match self.curr_tool {
+                Tool::Arrow => {
+                    //Round the direction to the nearest multiple of 45°
+                    let step = std::f32::consts::FRAC_PI_4;
+                    let angle = (delta.y.atan2(delta.x) / step).round() * step;
+                    start + Vec2::angled(angle) * delta.length()
+                }
+                _ => {
+                    let side = delta.x.abs().max(delta.y.abs());
+                    start + Vec2::new(side * delta.x.signum(), side * delta.y.signum())
+                }
+            };
+        }
+        if self.from_center {
+            return (start - (end - start), end);
+        }
+        (start, end)
+    }
+
+    /// Area covered by the annotation just drawn with the current tool, if it is one that can be used as a guide
+    pub fn annotation_bounds(&self) -> Option<Rect> {
+        let t = self.curr_thickness as f32;
+        let (start, end) = self.shape_points();
+        match self.curr_tool {
+            Tool::HollowRect | Tool::FilledRect | Tool::HollowEllipse | Tool::FilledEllipse | Tool::Arrow => Some(Rect::from_two_pos(start, end)),
+            Tool::HollowCircle | Tool::FilledCircle => {
+                let radius = (end - start).length();
+                Some(Rect::from_center_size(start, Vec2::splat(radius * 2.)).expand(t))
+            }
+            Tool::Drawing | Tool::Highlighter => self.stroke_bounds,
+            _ => None,
+        }
+    }
+
+    /// Draw the current measurement on [img]: the line, with ticks at its ends, and the distance next to it
+    pub fn burn_measurement(&self, img: &mut Image) {
+        let measure = &self.measure_info;
+        let start = match measure.start {
+            Some(start) => start,
+            None => return,
+        };
+        let end = measure.end;
+        let direction = (end - start).normalized();
+        let tick = Vec2::new(-direction.y, direction.x) * 6.;
+        draw_polyline_mut(img.pixels_mut(), &[(start.x, start.y), (end.x, end.y)], 1, self.curr_color, None);
+        for point in [start, end] {
+            draw_polyline_mut(img.pixels_mut(), &[((point - tick).x, (point - tick).y), ((point + tick).x, (point + tick).y)], 1, self.curr_color, None);
+        }
+        if let (Some(font), Some(label)) = (self.text_info.font().0, measure.label()) {
+            let middle = start + (end - start) / 2.;
+            draw_label_mut(img.pixels_mut(), font, &label, (middle.x + 8., middle.y + 8.), 16., [255, 255, 255, 255], [0, 0, 0, 170]);
+        }
+    }
+
+    /// Draw the contrast ratio of the two sampled colors on [img], next to where the second one was sampled
+    pub fn burn_contrast(&self, img: &mut Image) {
+        let (a, b, pos) = match self.measure_info.samples {
+            [Some((a, _)), Some((b, pos))] => (a, b, pos),
+            _ => return,
+        };
+        let ratio = contrast_ratio(a, b);
+        if let Some(font) = self.text_info.font().0 {
+            let swatch = 16.;
+            let (x, y) = (pos.x + 8., pos.y + 8.);
+            //The two colors, then the ratio
+            draw_blended_rect_mut(img.pixels_mut(), (x, y), (x + 2. * swatch + 8., y + swatch + 8.), [0, 0, 0, 170]);
+            draw_blended_rect_mut(img.pixels_mut(), (x + 4., y + 4.), (x + 4. + swatch, y + 4. + swatch), [a[0], a[1], a[2], 255]);
+            draw_blended_rect_mut(img.pixels_mut(), (x + 4. + swatch, y + 4.), (x + 4. + 2. * swatch, y + 4. + swatch), [b[0], b[1], b[2], 255]);
+            let text = format!("{:.2}:1 {}", ratio, wcag_level(ratio));
+            draw_label_mut(img.pixels_mut(), font, &text, (x + 2. * swatch + 8., y), 16., [255, 255, 255, 255], [0, 0, 0, 170]);
+        }
+    }
+
+    /// Draw the selection and the cursor of the text being edited, on top of the text already drawn by [apply_tool]
+    pub fn draw_text_cursor(&self, img: &mut Image) {
+        self.draw_buffer_cursor(img, &self.text_info.buffer, self.text_info.edge, self.text_info.block_width());
+    }
+
+    /// Draw the selection and the cursor of the text inside the callout being edited
+    pub fn draw_callout_cursor(&self, img: &mut Image) {
+        if let Some(bubble) = self.callout_box() {
+            let edge = bubble.min + Vec2::splat(CALLOUT_PADDING);
+            self.draw_buffer_cursor(img, &self.callout_info.buffer, edge, bubble.width() - 2. * CALLOUT_PADDING);
+        }
+    }
+
+    /// Draw selection and cursor of [buffer], whose lines start at [edge] and are aligned in a block [block_width] wide
+    fn draw_buffer_cursor(&self, img: &mut Image, buffer: &TextBuffer, edge: Pos2, block_width: f32) {
+        if !self.text_info.has_font() {
+            return;
+        }
+        let text_info = &self.text_info;
+        let dim = text_info.curr_dim as f32;
+        let lines = buffer.lines();
+        let x_of = |line: usize, col: usize| edge.x + text_info.line_offset(lines[line], block_width) + text_info.measure(&lines[line][..col]);
+        if let Some(range) = buffer.selection() {
+            let (start_line, start_col) = buffer.line_col(range.start);
+            let (end_line, end_col) = buffer.line_col(range.end);
+            for (line, text) in lines.iter().enumerate().take(end_line + 1).skip(start_line) {
+                let from = if line == start_line { x_of(line, start_col) } else { x_of(line, 0) };
+                let to = if line == end_line { x_of(line, end_col) } else { x_of(line, text.len()) + dim / 4. };
+                let y = edge.y + line as f32 * dim;
+                draw_blended_rect_mut(img.pixels_mut(), (from, y), (to, y + dim), [51, 153, 255, 90]);
+            }
+        }
+        let (line, col) = buffer.cursor_line_col();
+        let x = x_of(line, col);
+        let y = edge.y + line as f32 * dim;
+        drawing::draw_filled_rect_mut(img.pixels_mut(), imageproc::rect::Rect::at(x as i32, y as i32).of_size(max(1, (dim / 15.) as u32), max(1, dim as u32)), self.curr_color.into());
     }
 
-    /// Reset the paint state to its default values, excluding the current tool and color
-    pub fn soft_reset(&mut self) {
-        self.painting = false;
-        self.last_ptr = Pos2::default();
-        self.curr_ptr = Pos2::default();
+    /// Draw the spotlight in [area] of [img], which has to be the image it was started on, at least in that area
+    fn draw_spotlight(&self, img: &mut Image, area: Rect) {
+        let spotlight = &self.spotlight_info;
+        let areas: Vec<_> = spotlight.areas.iter().map(|(rect, shape)| SpotlightArea { min: (rect.min.x, rect.min.y), max: (rect.max.x, rect.max.y), ellipse: *shape == SpotShape::Ellipse }).collect();
+        let region = (area.left().floor().max(0.) as u32, area.top().floor().max(0.) as u32, area.right().ceil().max(0.) as u32, area.bottom().ceil().max(0.) as u32);
+        draw_spotlight_mut(img.pixels_mut(), &areas, spotlight.dim, spotlight.desaturate, spotlight.feather, region);
+    }
+
+    /// Draw again the spotlight only in [area] of [img], which already shows it everywhere else. Returns the area changed
+    pub fn redraw_spotlight(&self, img: &mut Image, area: Rect) -> Rect {
+        let area = area.intersect(whole_area(img));
+        if !area.is_positive() {
+            return Rect::NOTHING;
+        }
+        let (x, y) = (area.left().floor() as u32, area.top().floor() as u32);
+        let (width, height) = (area.right().ceil() as u32 - x, area.bottom().ceil() as u32 - y);
+        //What was below the old position of the areas comes back before drawing the new one
+        let original = self.spotlight_info.original_img.image.crop_imm(x, y, width, height);
+        match img.pixels_mut().copy_from(&original, x, y) {
+            Ok(_) => self.draw_spotlight(img, area),
+            Err(err) => println!("{}", err),
+        }
+        area
+    }
+
+    /// Box of the callout being edited, grown to fit its text
+    pub fn callout_box(&self) -> Option<Rect> {
+        let rect = self.callout_info.rect?;
+        let lines = self.callout_info.buffer.lines();
+        let text_width = lines.iter().map(|l| self.text_info.measure(l)).fold(0., f32::max);
+        let text_height = lines.len() as f32 * self.text_info.curr_dim as f32;
+        let width = rect.width().max(text_width + 2. * CALLOUT_PADDING);
+        let height = rect.height().max(text_height + 2. * CALLOUT_PADDING);
+        Some(Rect::from_min_size(rect.min, Vec2::new(width, height)))
     }
 
-    ///Draw a shape on the given img based on the field inside [self] ([curr_tool], [curr_color], [last_ptr], [curr_ptr], [curr_font], [curr_str])
-    pub fn apply_tool(&mut self, img: &mut Image, original_img: Option<DynamicImage>) {
-        let mut start_ptr = self.last_ptr;
-        let width = max(1, (self.curr_ptr.x - self.last_ptr.x).abs() as i32);
-        let height = max(1, (self.curr_ptr.y - self.last_ptr.y).abs() as i32);
+    ///Draw a shape on the given img based on the field inside [self] ([curr_tool], [curr_color], [last_ptr], [curr_ptr], [curr_family], [buffer])
+    pub fn apply_tool(&mut self, img: &mut Image, original_img: Option<&DynamicImage>) {
+        let (first_ptr, second_ptr) = self.shape_points();
+        let mut start_ptr = first_ptr;
+        let width = max(1, (second_ptr.x - first_ptr.x).abs() as i32);
+        let height = max(1, (second_ptr.y - first_ptr.y).abs() as i32);
         if self.curr_tool != Tool::Drawing && self.curr_tool != Tool::FilledCircle && self.curr_tool != Tool::HollowCircle {
             //Permits an easier selection, allowing to generate the area in all directions
-            if self.curr_ptr.x < self.last_ptr.x {
-                start_ptr.x = self.curr_ptr.x;
+            if second_ptr.x < first_ptr.x {
+                start_ptr.x = second_ptr.x;
             }
-            if self.curr_ptr.y < self.last_ptr.y {
-                start_ptr.y = self.curr_ptr.y;
+            if second_ptr.y < first_ptr.y {
+                start_ptr.y = second_ptr.y;
             }
         }
         match self.curr_tool {
             Tool::Drawing => {
-                draw_thick_line(&mut img.image, (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness, self.curr_color.into());
+                self.draw_pen_stroke(img, original_img.unwrap(), false);
             }
             Tool::HollowRect => {
-                draw_thick_hollow_rect_mut(&mut img.image, imageproc::rect::Rect::at(start_ptr.x as i32, start_ptr.y as i32).of_size(width as u32, height as u32), self.curr_thickness, self.curr_color.into());
+                draw_thick_hollow_rect_mut(img.pixels_mut(), imageproc::rect::Rect::at(start_ptr.x as i32, start_ptr.y as i32).of_size(width as u32, height as u32), self.curr_thickness, self.curr_color.into());
             }
             Tool::FilledRect => {
-                drawing::draw_filled_rect_mut(&mut img.image, imageproc::rect::Rect::at(start_ptr.x as i32, start_ptr.y as i32).of_size(width as u32, height as u32), self.curr_color.into());
+                drawing::draw_filled_rect_mut(img.pixels_mut(), imageproc::rect::Rect::at(start_ptr.x as i32, start_ptr.y as i32).of_size(width as u32, height as u32), self.curr_color.into());
             }
             Tool::HollowCircle => {
                 let radius = ((width.pow(2) + height.pow(2)) as f64).sqrt() as i32;
-                draw_thick_hollow_circle_mut(&mut img.image, (start_ptr.x as i32, start_ptr.y as i32), radius, self.curr_thickness, self.curr_color.into());
+                draw_thick_hollow_circle_mut(img.pixels_mut(), (start_ptr.x as i32, start_ptr.y as i32), radius, self.curr_thickness, self.curr_color.into());
             }
             Tool::FilledCircle => {
                 let radius = ((width.pow(2) + height.pow(2)) as f64).sqrt() as i32;
-                drawing::draw_filled_circle_mut(&mut img.image, (start_ptr.x as i32, start_ptr.y as i32), radius, self.curr_color.into());
+                drawing::draw_filled_circle_mut(img.pixels_mut(), (start_ptr.x as i32, start_ptr.y as i32), radius, self.curr_color.into());
+            }
+            Tool::HollowEllipse | Tool::FilledEllipse => {
+                let bounds = Rect::from_two_pos(first_ptr, second_ptr);
+                let thickness = if self.curr_tool == Tool::FilledEllipse { None } else { Some(self.curr_thickness) };
+                draw_ellipse_mut(img.pixels_mut(), (bounds.left(), bounds.top()), (bounds.right(), bounds.bottom()), thickness, self.curr_color);
             }
             Tool::Arrow => {
-                draw_arrow(&mut img.image, (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness, self.curr_color.into());
+                let ArrowGeometry { points, heads, size } = self.arrow_geometry();
+                let filled = self.arrow_style.head == ArrowHead::Filled;
+                draw_polyline_mut(img.pixels_mut(), &points, self.curr_thickness, self.curr_color, self.arrow_style.pattern.dash(self.curr_thickness));
+                for (tip, direction) in heads {
+                    draw_arrow_head_mut(img.pixels_mut(), (tip.x, tip.y), direction, size, self.curr_thickness, self.curr_color, filled);
+                }
             }
             Tool::Highlighter => {
-                highlight_line(original_img.as_ref().unwrap(), &mut img.image, (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness, self.curr_color.into());
-            }
-            Tool::Crop => {
-                let color = [0u8, 0u8, 0u8, 0u8];
-                drawing::draw_hollow_rect_mut(&mut img.image, imageproc::rect::Rect::at(start_ptr.x as i32, start_ptr.y as i32).of_size(width as u32, height as u32), color.into());
+                let (width, height) = (img.get_width(), img.get_height());
+                let mask = self.stroke_mask.get_or_insert_with(|| GrayImage::new(width, height));
+                let area = add_stroke_to_mask(mask, (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness as f32);
+                composite_highlight_mut(original_img.unwrap(), img.pixels_mut(), mask, area, self.curr_color, self.highlighter_opacity);
+                self.mark_changed(Rect::from_min_max(Pos2::new(area.0 as f32, area.1 as f32), Pos2::new(area.2 as f32, area.3 as f32)));
             }
             Tool::Eraser => {
-                erase_thick_line(original_img.as_ref().unwrap(), &mut img.image, (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness);
+                match self.eraser_mode {
+                    EraserMode::Pixels => {
+                        erase_thick_line(original_img.unwrap(), img.pixels_mut(), (self.last_ptr.x, self.last_ptr.y), (self.curr_ptr.x, self.curr_ptr.y), self.curr_thickness);
+                        self.mark_changed(Rect::from_two_pos(self.last_ptr, self.curr_ptr).expand(self.curr_thickness as f32 + 2.));
+                    }
+                    EraserMode::Objects => {
+                        if img.erase_annotations(self.last_ptr, self.curr_ptr, self.curr_thickness as f32) {
+                            self.mark_changed(whole_area(img));
+                        }
+                    }
+                }
             }
             Tool::Text => {
                 //Unwrap cannot panic, text mode is allowed only if font loaded correctly
-                let lines:Vec<&str> = self.text_info.curr_str.split("\n").collect();
-                let mut y = self.text_info.edge.y;
+                let text_info = &self.text_info;
+                let style = &text_info.style;
+                let (font, fake_bold, fake_italic) = text_info.font();
+                let dim = text_info.curr_dim as f32;
+                let font = StyledFont { font: font.unwrap(), scale: Scale::uniform(dim), fake_bold, fake_italic };
+                let lines = text_info.buffer.lines();
+                let block_width = text_info.block_width();
+                if style.background {
+                    let padding = style.padding as f32;
+                    let min = (text_info.edge.x - padding, text_info.edge.y - padding);
+                    let max = (text_info.edge.x + block_width + padding, text_info.edge.y + lines.len() as f32 * dim + padding);
+                    draw_blended_rect_mut(img.pixels_mut(), min, max, style.background_color);
+                }
+                let mut y = text_info.edge.y;
                 for l in lines{
-                    drawing::draw_text_mut(&mut img.image, self.curr_color.into(), self.text_info.edge.x as i32, y as i32, rusttype::Scale::uniform(self.text_info.curr_dim as f32), self.text_info.curr_font.as_ref().unwrap(), l);
-                    y += self.text_info.curr_dim as f32;
+                    let x = text_info.edge.x + text_info.line_offset(l, block_width);
+                    if style.outline {
+                        draw_text_outline_mut(img.pixels_mut(), style.outline_color, (x, y), font, l, style.outline_width);
+                    }
+                    draw_styled_text_mut(img.pixels_mut(), self.curr_color, (x, y), font, l);
+                    y += dim;
+                }
+            }
+            Tool::Magnifier => {
+                let magnifier = &self.magnifier_info;
+                if let Some(source) = magnifier.source {
+                    let center = magnifier.inset_center;
+                    let geometry = MagnifierGeometry {
+                        min: (source.left(), source.top()),
+                        max: (source.right(), source.bottom()),
+                        inset_center: (center.x, center.y),
+                        factor: magnifier.factor,
+                        circle: magnifier.shape == InsetShape::Circle,
+                    };
+                    draw_magnifier_mut(img.pixels_mut(), &magnifier.original_img.image, geometry, self.curr_thickness, self.curr_color);
+                }
+            }
+            Tool::Spotlight => {
+                self.draw_spotlight(img, whole_area(img));
+            }
+            Tool::Insert => {
+                let rect = self.insert_info.rect;
+                if let (Some(rect), Some(content)) = (rect, self.insert_info.scaled_content()) {
+                    image::imageops::overlay(img.pixels_mut(), content, rect.min.x.round() as i64, rect.min.y.round() as i64);
+                }
+            }
+            Tool::Callout => {
+                if let Some(bubble) = self.callout_box() {
+                    let tail = self.callout_info.tail;
+                    draw_callout_mut(img.pixels_mut(), (bubble.left(), bubble.top()), (bubble.right(), bubble.bottom()), (tail.x, tail.y), self.curr_thickness, self.curr_color, self.callout_info.fill(self.curr_color));
+                    //The text uses the font and style of the text tool, inside the padding of the box
+                    let text_info = &self.text_info;
+                    if let (Some(font), fake_bold, fake_italic) = text_info.font() {
+                        let dim = text_info.curr_dim as f32;
+                        let font = StyledFont { font, scale: Scale::uniform(dim), fake_bold, fake_italic };
+                        let block_width = bubble.width() - 2. * CALLOUT_PADDING;
+                        let mut y = bubble.top() + CALLOUT_PADDING;
+                        for l in self.callout_info.buffer.lines() {
+                            let x = bubble.left() + CALLOUT_PADDING + text_info.line_offset(l, block_width);
+                            draw_styled_text_mut(img.pixels_mut(), self.curr_color, (x, y), font, l);
+                            y += dim;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Zoom levels proposed in the zoom selector
+pub const ZOOM_PRESETS: [f32; 8] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0, 8.0];
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 16.0;
+
+/// View transform of the canvas: how much the image is scaled and where the scroll area is looking at
+pub struct ViewState {
+    pub zoom: f32,
+    pub fit_to_window: bool,
+    //Scroll offset and screen rect of the scroll area during the last frame, needed to zoom around the pointer
+    offset: Vec2,
+    inner_rect: Rect,
+}
+
+impl ViewState {
+    pub fn new() -> Self {
+        ViewState {
+            zoom: 1.0,
+            fit_to_window: false,
+            offset: Vec2::ZERO,
+            inner_rect: Rect::NOTHING,
+        }
+    }
+
+    /// Set a fixed zoom level, leaving the fit-to-window mode
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.fit_to_window = false;
+    }
+
+    /// Compute the zoom needed to show the whole image inside [available], never enlarging it
+    pub fn fit(&mut self, available: Vec2, width: u32, height: u32) {
+        let zoom = (available.x / width as f32).min(available.y / height as f32).min(1.0);
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    /// Multiply the zoom by [factor], returning the scroll offset that keeps the content under [anchor] still
+    pub fn zoom_around(&mut self, factor: f32, anchor: Pos2) -> Vec2 {
+        let old_zoom = self.zoom;
+        self.set_zoom(self.zoom * factor);
+        let local = anchor - self.inner_rect.min;
+        (self.offset + local) * (self.zoom / old_zoom) - local
+    }
+
+    /// True if [pos] is on the visible part of the canvas
+    pub fn viewport_contains(&self, pos: Pos2) -> bool {
+        self.inner_rect.contains(pos)
+    }
+
+    /// Store the state of the scroll area at the end of the frame
+    pub fn update_viewport(&mut self, offset: Vec2, inner_rect: Rect) {
+        self.offset = offset;
+        self.inner_rect = inner_rect;
+    }
+}
+
+/// Texture of the image shown on the canvas. While editing it is uploaded again only where the image changes
+pub struct CanvasTexture {
+    texture: Option<TextureHandle>,
+    options: TextureOptions,
+    //Whether the texture shows the image being edited, which can be updated in parts, instead of the preview of the final image
+    editing: bool,
+}
+
+impl CanvasTexture {
+    pub fn new() -> Self {
+        CanvasTexture { texture: None, options: TextureOptions::LINEAR, editing: false }
+    }
+
+    /// Show the whole [image]
+    pub fn show(&mut self, ctx: &Context, image: &DynamicImage, options: TextureOptions) -> &TextureHandle {
+        let color_image = ColorImage::from_rgba_unmultiplied([image.width() as usize, image.height() as usize], image.as_bytes());
+        match &mut self.texture {
+            Some(texture) => texture.set(color_image, options),
+            None => self.texture = Some(ctx.load_texture("screenshot", color_image, options)),
+        }
+        self.options = options;
+        self.editing = false;
+        self.texture.as_ref().unwrap()
+    }
+
+    /// Show the preview of the final image, uploading it only if it [changed] or the texture shows something else
+    pub fn show_preview(&mut self, ctx: &Context, preview: &DynamicImage, changed: bool, options: TextureOptions) -> &TextureHandle {
+        if changed || self.editing || self.options != options || self.texture.is_none() {
+            return self.show(ctx, preview, options);
+        }
+        self.texture.as_ref().unwrap()
+    }
+
+    /// Show the image being edited, uploading only its [changed] area when the texture already shows the rest
+    pub fn show_editing(&mut self, ctx: &Context, image: &Image, changed: Option<Rect>, options: TextureOptions) -> &TextureHandle {
+        let size = [image.get_width() as usize, image.get_height() as usize];
+        let current = match &self.texture {
+            Some(texture) => self.editing && self.options == options && texture.size() == size,
+            None => false,
+        };
+        if !current {
+            self.show(ctx, &image.get_composite(), options);
+            self.editing = true;
+            return self.texture.as_ref().unwrap();
+        }
+        let area = match changed {
+            Some(area) => area.intersect(whole_area(image)),
+            None => Rect::NOTHING,
+        };
+        if area.width() > 0. && area.height() > 0. {
+            let (x, y) = (area.min.x.floor() as u32, area.min.y.floor() as u32);
+            let (width, height) = (area.max.x.ceil() as u32 - x, area.max.y.ceil() as u32 - y);
+            let part = image.composite_area(x, y, width, height);
+            let color_image = ColorImage::from_rgba_unmultiplied([part.width() as usize, part.height() as usize], part.as_bytes());
+            self.texture.as_mut().unwrap().set_partial([x as usize, y as usize], color_image, options);
+        }
+        self.texture.as_ref().unwrap()
+    }
+}
+
+/// What a layer contains, which decides how it is erased: the capture is restored, the others become transparent
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum LayerKind {
+    Capture,
+    Annotations,
+    Picture,
+}
+
+/// How a layer is combined with the layers below it
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 6] = [BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen, BlendMode::Overlay, BlendMode::Darken, BlendMode::Lighten];
+
+    /// Mix a channel of the layers [below] with the one of the layer [above], both between 0 and 1
+    pub fn mix(&self, below: f32, above: f32) -> f32 {
+        match self {
+            BlendMode::Normal => above,
+            BlendMode::Multiply => below * above,
+            BlendMode::Screen => below + above - below * above,
+            BlendMode::Overlay if below <= 0.5 => 2. * below * above,
+            BlendMode::Overlay => 1. - 2. * (1. - below) * (1. - above),
+            BlendMode::Darken => below.min(above),
+            BlendMode::Lighten => below.max(above),
+        }
+    }
+}
+
+impl Display for BlendMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+            BlendMode::Overlay => "Overlay",
+            BlendMode::Darken => "Darken",
+            BlendMode::Lighten => "Lighten",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Color adjustments of the capture, applied when the layers are combined so that they can be changed or removed at any time
+#[derive(Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    //From -1 to 1, 0 leaves the image as it is
+    pub brightness: f32,
+    pub contrast: f32,
+    //1 leaves the image as it is, higher values brighten the dark tones
+    pub gamma: f32,
+    //From 0, gray, to 2, twice as saturated
+    pub saturation: f32,
+    pub grayscale: bool,
+    pub invert: bool,
+    //Strength of the sharpening filter, 0 for none
+    pub sharpen: f32,
+}
+
+impl Adjustments {
+    pub fn new() -> Self {
+        Adjustments { brightness: 0., contrast: 0., gamma: 1., saturation: 1., grayscale: false, invert: false, sharpen: 0. }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::new()
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let mut rgba = img.to_rgba8();
+        if self.brightness != 0. {
+            image::imageops::colorops::brighten_in_place(&mut rgba, (self.brightness * 255.).round() as i32);
+        }
+        if self.contrast != 0. {
+            image::imageops::colorops::contrast_in_place(&mut rgba, self.contrast * 100.);
+        }
+        if self.gamma != 1. || self.saturation != 1. || self.grayscale {
+            let gamma: Vec<u8> = (0..256).map(|v| ((v as f32 / 255.).powf(1. / self.gamma) * 255.).round() as u8).collect();
+            let saturation = if self.grayscale { 0. } else { self.saturation };
+            for pixel in rgba.pixels_mut() {
+                let [r, g, b, _] = pixel.0.map(|c| c as f32);
+                let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+                for c in 0..3 {
+                    let saturated = luma + (pixel.0[c] as f32 - luma) * saturation;
+                    pixel.0[c] = gamma[saturated.round().clamp(0., 255.) as usize];
                 }
+            }
+        }
+        if self.invert {
+            image::imageops::colorops::invert(&mut rgba);
+        }
+        if self.sharpen > 0. {
+            let a = self.sharpen;
+            rgba = imageproc::filter::filter3x3::<_, f32, u8>(&rgba, &[0., -a, 0., -a, 1. + 4. * a, -a, 0., -a, 0.]);
+        }
+        DynamicImage::ImageRgba8(rgba)
+    }
+}
+
+/// Layer of an image, drawn over the ones before it
+#[derive(Clone)]
+pub struct Layer {
+    pub name: String,
+    pub kind: LayerKind,
+    pub visible: bool,
+    pub opacity: f32,
+    pub blend: BlendMode,
+    pub adjustments: Adjustments,
+    //Pixels with the adjustments applied, computed the first time they are needed and dropped when the pixels or the adjustments change
+    adjusted: OnceCell<Rc<DynamicImage>>,
+    //Pixels and annotations of the layer, moved into the image while the layer is the active one.
+    //The pixels are compressed as the images of the undo stack, and shared by all of them as they change only while the layer is active
+    pixels: Option<Rc<Vec<u8>>>,
+    //Pixels decompressed the first time they are needed, not kept in the undo stack
+    decoded: OnceCell<Rc<DynamicImage>>,
+    annotations: Vec<Annotation>,
+}
+
+impl Layer {
+    fn new(name: String, kind: LayerKind) -> Self {
+        Layer { name, kind, visible: true, opacity: 1., blend: BlendMode::Normal, adjustments: Adjustments::new(), adjusted: OnceCell::new(), pixels: None, decoded: OnceCell::new(), annotations: Vec::new() }
+    }
+
+    /// The capture alone, being edited
+    fn base() -> Vec<Layer> {
+        vec![Layer::new("Capture".to_string(), LayerKind::Capture)]
+    }
+
+    /// Pixels of the layer while it is not the active one
+    fn get_pixels(&self) -> &DynamicImage {
+        self.decoded.get_or_init(|| Rc::new(decompress_image(self.pixels.as_ref().unwrap())))
+    }
+
+    fn set_pixels(&mut self, pixels: DynamicImage) {
+        self.pixels = Some(Rc::new(compress_image(&pixels)));
+        self.decoded = OnceCell::from(Rc::new(pixels));
+    }
+
+    /// Take the pixels out of the layer, as it becomes the active one
+    fn take_pixels(&mut self) -> DynamicImage {
+        let pixels = match self.decoded.take() {
+            Some(decoded) => Rc::try_unwrap(decoded).unwrap_or_else(|decoded| (*decoded).clone()),
+            None => decompress_image(self.pixels.as_ref().unwrap()),
+        };
+        self.pixels = None;
+        pixels
+    }
+
+    /// The layer as it is kept in the undo stack, only compressed
+    fn stored(self) -> Layer {
+        Layer { adjusted: OnceCell::new(), decoded: OnceCell::new(), ..self }
+    }
+}
 
+/// Change to the layers of an image, made from the layers window
+pub enum LayerChange {
+    Select(usize),
+    Show(usize, bool),
+    Move(usize, bool),
+    Remove(usize),
+    Add(LayerKind, Option<DynamicImage>),
+    Opacity(f32),
+    Blend(BlendMode),
+}
 
+impl LayerChange {
+    pub fn apply(self, img: &mut Image) {
+        match self {
+            LayerChange::Select(index) => img.set_active_layer(index),
+            LayerChange::Show(index, visible) => img.layer_mut(index).visible = visible,
+            LayerChange::Move(index, up) => img.move_layer(index, up),
+            LayerChange::Remove(index) => img.remove_layer(index),
+            LayerChange::Add(kind, pixels) => {
+                let (width, height) = (img.get_width(), img.get_height());
+                let (name, pixels) = match pixels {
+                    //Pictures are centered on the image, cut where they don't fit
+                    Some(picture) => {
+                        let mut canvas = RgbaImage::new(width, height);
+                        let x = (width as i64 - picture.width() as i64) / 2;
+                        let y = (height as i64 - picture.height() as i64) / 2;
+                        image::imageops::overlay(&mut canvas, &picture.to_rgba8(), x, y);
+                        (format!("Picture {}", img.layers.len() + 1), Some(DynamicImage::ImageRgba8(canvas)))
+                    }
+                    None => (format!("Layer {}", img.layers.len() + 1), None),
+                };
+                img.add_layer(name, kind, pixels);
+            }
+            LayerChange::Opacity(opacity) => img.layer_mut(img.active_layer).opacity = opacity,
+            LayerChange::Blend(blend) => img.layer_mut(img.active_layer).blend = blend,
+        }
+    }
+}
+
+/// Pixels changed by an annotation, in the smallest area containing all of them
+#[derive(Clone)]
+struct Patch {
+    x: u32,
+    y: u32,
+    //Pixels of the area before and after the annotation, compressed as the images of the undo stack
+    before: Vec<u8>,
+    after: Vec<u8>,
+    //Kept as it is, the eraser looks at it at every move
+    changed: GrayImage,
+}
+
+impl Patch {
+    /// Compare the image [before] and [after] an annotation was drawn. None if nothing changed
+    fn new(before: &DynamicImage, after: &DynamicImage) -> Option<Patch> {
+        let (width, height) = (after.width(), after.height());
+        if before.width() != width || before.height() != height || before.color() != after.color() || width == 0 {
+            return None;
+        }
+        let (old, new) = (before.as_bytes(), after.as_bytes());
+        let row = old.len() / height as usize;
+        let bpp = row / width as usize;
+        //Whole rows are compared first, they are equal for most of the image
+        let rows: Vec<u32> = (0..height).filter(|&y| {
+            let range = row * y as usize..row * (y as usize + 1);
+            old[range.clone()] != new[range]
+        }).collect();
+        let (y0, y1) = (*rows.first()?, *rows.last()? + 1);
+        let (mut x0, mut x1) = (width, 0);
+        for y in y0..y1 {
+            for x in 0..width {
+                let i = row * y as usize + bpp * x as usize;
+                if old[i..i + bpp] != new[i..i + bpp] {
+                    x0 = x0.min(x);
+                    x1 = x1.max(x + 1);
+                }
+            }
+        }
+        let before = before.crop_imm(x0, y0, x1 - x0, y1 - y0).to_rgba8();
+        let after = after.crop_imm(x0, y0, x1 - x0, y1 - y0).to_rgba8();
+        let changed = GrayImage::from_fn(x1 - x0, y1 - y0, |x, y| Luma([if before.get_pixel(x, y) != after.get_pixel(x, y) { 255 } else { 0 }]));
+        let (before, after) = (compress_image(&DynamicImage::ImageRgba8(before)), compress_image(&DynamicImage::ImageRgba8(after)));
+        Some(Patch { x: x0, y: y0, before, after, changed })
+    }
+
+    fn before(&self) -> RgbaImage {
+        decompress_image(&self.before).into_rgba8()
+    }
+
+    fn after(&self) -> RgbaImage {
+        decompress_image(&self.after).into_rgba8()
+    }
+
+    /// Whether the pixel (x, y) of the image was changed by the annotation
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.changed.width() && y < self.y + self.changed.height()
+            && self.changed.get_pixel(x - self.x, y - self.y).0[0] != 0
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_min_size(Pos2::new(self.x as f32, self.y as f32), Vec2::new(self.changed.width() as f32, self.changed.height() as f32))
+    }
+
+    /// Whether a pixel changed by the annotation is within [radius] from the segment between [start] and [end]
+    fn touches(&self, start: Pos2, end: Pos2, radius: f32) -> bool {
+        if !self.area().expand(radius + 1.).intersects(Rect::from_two_pos(start, end)) {
+            return false;
+        }
+        for (x, y, pixel) in self.changed.enumerate_pixels() {
+            let center = ((self.x + x) as f32 + 0.5, (self.y + y) as f32 + 0.5);
+            if pixel.0[0] != 0 && segment_distance(center, (start.x, start.y), (end.x, end.y)) <= radius + 0.5 {
+                return true;
             }
-            _ => {}
         }
+        false
     }
 }
 
+/// Annotation drawn on an image: its bounds, used as smart guides, and the pixels it changed, to remove it as a whole.
+/// The pixels are shared by all the images of the undo stack having the annotation
+#[derive(Clone)]
+pub struct Annotation {
+    pub bounds: Rect,
+    patch: Option<Rc<Patch>>,
+    //Samples of a freehand stroke, so that it can be drawn again. Empty for the other annotations
+    pub stroke: Rc<[StrokePoint]>,
+}
+
+impl Annotation {
+    /// Whether the annotation has a pixel within [radius] from the segment between [start] and [end].
+    /// Freehand strokes are checked along their samples, the other annotations pixel by pixel
+    fn touches(&self, start: Pos2, end: Pos2, radius: f32) -> bool {
+        if self.stroke.is_empty() {
+            return match &self.patch {
+                Some(patch) => patch.touches(start, end, radius),
+                None => false,
+            };
+        }
+        //The first sample alone is also a segment, for strokes that are a single dot
+        let mut segments = self.stroke.windows(2).map(|w| (w[0], w[1])).chain(self.stroke.first().map(|&p| (p, p)));
+        segments.any(|(a, b)| segments_distance((a.pos.x, a.pos.y), (b.pos.x, b.pos.y), (start.x, start.y), (end.x, end.y)) <= radius + a.radius.max(b.radius))
+    }
+}
 
 #[derive(Clone)]
 pub struct Image {
     image: DynamicImage,
     crop_index: usize,
+    //Annotations drawn on the active layer since it was last cropped or transformed
+    annotations: Vec<Annotation>,
+    //Layers from the bottom one, [image] has the pixels of the active one, the one the tools draw on
+    layers: Vec<Layer>,
+    active_layer: usize,
 }
 
 impl Image {
     pub fn new(image: DynamicImage, crop_index: usize) -> Self {
-        Image { image, crop_index }
+        Image { image, crop_index, annotations: Vec::new(), layers: Layer::base(), active_layer: 0 }
+    }
+
+    pub fn get_layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> &mut Layer {
+        &mut self.layers[index]
+    }
+
+    pub fn get_active_layer(&self) -> usize {
+        self.active_layer
+    }
+
+    /// Pixels of the active layer, to draw on them
+    fn pixels_mut(&mut self) -> &mut DynamicImage {
+        self.layers[self.active_layer].adjusted.take();
+        &mut self.image
+    }
+
+    fn layer_pixels(&self, index: usize) -> &DynamicImage {
+        if index == self.active_layer {
+            &self.image
+        } else {
+            self.layers[index].get_pixels()
+        }
+    }
+
+    /// Make the layer at [index] the one the tools draw on
+    pub fn set_active_layer(&mut self, index: usize) {
+        if index == self.active_layer || index >= self.layers.len() {
+            return;
+        }
+        let pixels = self.layers[index].take_pixels();
+        let annotations = std::mem::take(&mut self.layers[index].annotations);
+        self.activate(index, pixels, annotations);
+    }
+
+    /// Make the layer at [index], having [pixels] and [annotations], the active one, storing the one active until now
+    fn activate(&mut self, index: usize, pixels: DynamicImage, annotations: Vec<Annotation>) {
+        let old_pixels = std::mem::replace(&mut self.image, pixels);
+        let old_annotations = std::mem::replace(&mut self.annotations, annotations);
+        let old = self.active_layer;
+        self.layers[old].set_pixels(old_pixels);
+        self.layers[old].annotations = old_annotations;
+        self.active_layer = index;
+    }
+
+    /// Pixels of the layer at [index] with its adjustments applied
+    fn adjusted_pixels(&self, index: usize) -> &DynamicImage {
+        let layer = &self.layers[index];
+        layer.adjusted.get_or_init(|| Rc::new(layer.adjustments.apply(self.layer_pixels(index))))
+    }
+
+    /// Adjustments of the capture layer, None if it was removed
+    pub fn get_adjustments(&self) -> Option<Adjustments> {
+        self.layers.iter().find(|layer| layer.kind == LayerKind::Capture).map(|layer| layer.adjustments)
+    }
+
+    pub fn set_adjustments(&mut self, adjustments: Adjustments) {
+        if let Some(index) = self.layers.iter().position(|layer| layer.kind == LayerKind::Capture) {
+            self.layers[index].adjustments = adjustments;
+            self.layers[index].adjusted.take();
+        }
+    }
+
+    /// Add a layer above the active one and make it active. Without [pixels] the layer is transparent
+    pub fn add_layer(&mut self, name: String, kind: LayerKind, pixels: Option<DynamicImage>) {
+        let pixels = match pixels {
+            Some(pixels) => DynamicImage::ImageRgba8(pixels.to_rgba8()),
+            None => DynamicImage::new_rgba8(self.image.width(), self.image.height()),
+        };
+        let index = self.active_layer + 1;
+        self.layers.insert(index, Layer::new(name, kind));
+        self.activate(index, pixels, Vec::new());
+    }
+
+    /// Remove the layer at [index], unless it is the only one
+    pub fn remove_layer(&mut self, index: usize) {
+        if self.layers.len() < 2 {
+            return;
+        }
+        if index == self.active_layer {
+            self.set_active_layer(if index > 0 { index - 1 } else { 1 });
+        }
+        self.layers.remove(index);
+        if self.active_layer > index {
+            self.active_layer -= 1;
+        }
+    }
+
+    /// Swap the layer at [index] with the one above it, or below it if not [up]
+    pub fn move_layer(&mut self, index: usize, up: bool) {
+        let other = if up { index + 1 } else { index.wrapping_sub(1) };
+        if other >= self.layers.len() {
+            return;
+        }
+        self.layers.swap(index, other);
+        if self.active_layer == index {
+            self.active_layer = other;
+        } else if self.active_layer == other {
+            self.active_layer = index;
+        }
+    }
+
+    /// Apply [f] to every layer, as for crops and transformations. The annotations are dropped, they don't match the pixels anymore
+    pub fn map_layers(&self, crop_index: usize, f: impl Fn(&DynamicImage) -> DynamicImage) -> Image {
+        let mut img = Image::new(f(&self.image), crop_index);
+        img.layers = self.layers.iter().enumerate().map(|(index, layer)| {
+            let mut mapped = Layer { adjusted: OnceCell::new(), pixels: None, decoded: OnceCell::new(), annotations: Vec::new(), ..layer.clone() };
+            if index != self.active_layer {
+                mapped.set_pixels(f(layer.get_pixels()));
+            }
+            mapped
+        }).collect();
+        img.active_layer = self.active_layer;
+        img
+    }
+
+    /// The visible layers drawn one over the other, as the image is shown and exported
+    pub fn get_composite(&self) -> DynamicImage {
+        let layer = &self.layers[0];
+        //With only the capture there is nothing to combine
+        if self.layers.len() == 1 && layer.visible && layer.opacity >= 1. && layer.adjustments.is_identity() {
+            return self.image.clone();
+        }
+        self.composite_area(0, 0, self.image.width(), self.image.height())
+    }
+
+    /// The visible layers drawn one over the other, only in the given area
+    pub fn composite_area(&self, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
+        let width = width.min(self.image.width().saturating_sub(x));
+        let height = height.min(self.image.height().saturating_sub(y));
+        let mut canvas = RgbaImage::new(width, height);
+        for (i, layer) in self.layers.iter().enumerate() {
+            if !layer.visible {
+                continue;
+            }
+            let whole = width == self.image.width() && height == self.image.height();
+            let pixels = match layer.adjusted.get() {
+                _ if layer.adjustments.is_identity() => self.layer_pixels(i).crop_imm(x, y, width, height).to_rgba8(),
+                Some(adjusted) => adjusted.crop_imm(x, y, width, height).to_rgba8(),
+                //While the active layer is edited only the area changed is adjusted, the whole layer once it is shown again
+                None if i == self.active_layer && !whole => layer.adjustments.apply(&self.layer_pixels(i).crop_imm(x, y, width, height)).to_rgba8(),
+                None => self.adjusted_pixels(i).crop_imm(x, y, width, height).to_rgba8(),
+            };
+            blend_layer_mut(&mut canvas, &pixels, layer.opacity, |below, above| layer.blend.mix(below, above));
+        }
+        DynamicImage::ImageRgba8(canvas)
+    }
+
+    pub fn get_annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Record the annotation within [bounds] just drawn on the image, which was [before] without it
+    pub fn add_annotation(&mut self, bounds: Rect, before: &Image) {
+        self.add_stroke_annotation(bounds, before, &[]);
+    }
+
+    /// Same as [add_annotation], for a freehand stroke keeping its samples
+    pub fn add_stroke_annotation(&mut self, bounds: Rect, before: &Image, stroke: &[StrokePoint]) {
+        let patch = Patch::new(&before.image, &self.image).map(Rc::new);
+        self.annotations.push(Annotation { bounds, patch, stroke: Rc::from(stroke) });
+    }
+
+    /// Remove the annotations with a pixel within [radius] from the segment between [start] and [end],
+    /// restoring what was below them. Returns true if any annotation was removed
+    pub fn erase_annotations(&mut self, start: Pos2, end: Pos2, radius: f32) -> bool {
+        let mut removed = false;
+        for i in (0..self.annotations.len()).rev() {
+            if self.annotations[i].touches(start, end, radius) {
+                self.remove_annotation(i);
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    fn remove_annotation(&mut self, index: usize) {
+        let patch = match self.annotations.remove(index).patch {
+            Some(patch) => patch,
+            None => return,
+        };
+        let mut below = patch.before();
+        //The annotations drawn later over the same pixels go back on top, and what is below them changes
+        for later in self.annotations[index..].iter_mut() {
+            match &mut later.patch {
+                Some(later_patch) if later_patch.area().intersects(patch.area()) => {
+                    let (mut later_before, later_after) = (later_patch.before(), later_patch.after());
+                    let mut covered = false;
+                    for (x, y, pixel) in patch.changed.enumerate_pixels() {
+                        if pixel.0[0] == 0 || !later_patch.contains(patch.x + x, patch.y + y) {
+                            continue;
+                        }
+                        let (px, py) = (patch.x + x - later_patch.x, patch.y + y - later_patch.y);
+                        later_before.put_pixel(px, py, *below.get_pixel(x, y));
+                        below.put_pixel(x, y, *later_after.get_pixel(px, py));
+                        covered = true;
+                    }
+                    if covered {
+                        Rc::make_mut(later_patch).before = compress_image(&DynamicImage::ImageRgba8(later_before));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (x, y, pixel) in patch.changed.enumerate_pixels() {
+            if pixel.0[0] != 0 {
+                self.pixels_mut().draw_pixel(patch.x + x, patch.y + y, *below.get_pixel(x, y));
+            }
+        }
     }
-    pub fn get_image(&self) -> DynamicImage {
+    pub fn _get_image(&self) -> DynamicImage {
         self.image.clone()
     }
 
+    /// Pixels of the capture layer, active or not. Transparent if the capture layer was removed
+    pub fn get_capture_image(&self) -> DynamicImage {
+        match self.layers.iter().position(|layer| layer.kind == LayerKind::Capture) {
+            Some(index) => self.layer_pixels(index).clone(),
+            None => DynamicImage::new_rgba8(self.get_width(), self.get_height()),
+        }
+    }
+
+    /// Color under [pos] in the visible layers, averaged over a square of side [size]
+    pub fn sample(&self, pos: Pos2, size: u32) -> [u8; 4] {
+        let (x, y) = (pos.x.max(0.) as u32, pos.y.max(0.) as u32);
+        if self.layers.len() == 1 && self.layers[0].adjustments.is_identity() {
+            return sample_color(&self.image, x, y, size);
+        }
+        //Only the layers around the point are combined
+        let (x0, y0) = (x.saturating_sub(size), y.saturating_sub(size));
+        sample_color(&self.composite_area(x0, y0, 2 * size + 1, 2 * size + 1), x - x0, y - y0, size)
+    }
+
     pub fn get_crop_index(&self) -> usize {
         self.crop_index
     }
@@ -219,44 +2526,57 @@ impl Image {
     }
 }
 
+/// Encode the RGBA pixels of [image] as PNG, to keep it in memory in little space
+fn compress_image(image: &DynamicImage) -> Vec<u8> {
+    let rgba_data = image.as_bytes();
+    let mut png_data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(Cursor::new(&mut png_data), image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(rgba_data).unwrap();
+    }
+    png_data
+}
+
+/// Decode an image encoded by [compress_image]
+fn decompress_image(png_data: &[u8]) -> DynamicImage {
+    // Create a decoder for the PNG data
+    let cursor = Cursor::new(png_data);
+    let decoder = Decoder::new(cursor);
+
+    // Read the PNG data and decode it
+    let mut reader = decoder.read_info().unwrap();
+    let mut image_data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut image_data).unwrap();
+
+    // Convert the decoded image data to a DynamicImage
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(
+        reader.info().width,
+        reader.info().height,
+        image_data,
+    ).unwrap())
+}
+
 #[derive(Clone)]
 pub struct CompressedImage {
     image: Vec<u8>,
     crop_index: usize,
+    annotations: Vec<Annotation>,
+    layers: Vec<Layer>,
+    active_layer: usize,
+    //Guides of the image, found the first time a shape is drawn on it
+    guides: OnceCell<Rc<Guides>>,
 }
 
 impl CompressedImage {
     pub fn new(image: DynamicImage, crop_index: usize) -> Self {
-        let rgba_data = image.as_bytes();
-        let mut png_data = Vec::new();
-        {
-            let mut encoder = png::Encoder::new(Cursor::new(&mut png_data), image.width(), image.height());
-            encoder.set_color(png::ColorType::Rgba);
-            encoder.set_depth(png::BitDepth::Eight);
-
-            let mut writer = encoder.write_header().unwrap();
-            writer.write_image_data(&rgba_data).unwrap();
-        }
-        CompressedImage { image: png_data, crop_index }
+        CompressedImage { image: compress_image(&image), crop_index, annotations: Vec::new(), layers: Layer::base(), active_layer: 0, guides: OnceCell::new() }
     }
     pub fn get_decompressed_image(&self) -> DynamicImage {
-        let png_data = self.image.clone();
-        // Create a decoder for the PNG data
-        let cursor = Cursor::new(png_data);
-        let decoder = Decoder::new(cursor);
-
-        // Read the PNG data and decode it
-        let mut reader = decoder.read_info().unwrap();
-        let mut image_data = vec![0; reader.output_buffer_size()];
-        reader.next_frame(&mut image_data).unwrap();
-
-        // Convert the decoded image data to a DynamicImage
-        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(
-            reader.info().width,
-            reader.info().height,
-            image_data,
-        ).unwrap());
-        return image;
+        decompress_image(&self.image)
     }
 
     pub fn get_crop_index(&self) -> usize {
@@ -266,24 +2586,41 @@ impl CompressedImage {
 
 impl Into<CompressedImage> for Image {
     fn into(self) -> CompressedImage {
-        CompressedImage::new(self.image, self.crop_index)
+        let mut compressed = CompressedImage::new(self.image, self.crop_index);
+        compressed.annotations = self.annotations;
+        compressed.layers = self.layers.into_iter().map(Layer::stored).collect();
+        compressed.active_layer = self.active_layer;
+        compressed
     }
 }
 
 impl Into<Image> for CompressedImage {
     fn into(self) -> Image {
-        Image::new(self.get_decompressed_image(), self.get_crop_index())
+        let mut image = Image::new(self.get_decompressed_image(), self.get_crop_index());
+        image.annotations = self.annotations;
+        image.layers = self.layers;
+        image.active_layer = self.active_layer;
+        image
     }
 }
 
+/// Maximum difference of each channel for a pixel to be considered part of a uniform border
+const TRIM_TOLERANCE: u8 = 8;
+
 pub struct ImageStack {
     images: VecDeque<CompressedImage>,
     redo_images: VecDeque<CompressedImage>,
     crop_images: Vec<DynamicImage>,
     pub tmp_image: Image,
+    //Area of [tmp_image] changed since it was last shown
+    tmp_changed: Option<Rect>,
     final_image: Image,
 }
 
+fn whole_area(image: &Image) -> Rect {
+    Rect::from_min_size(Pos2::ZERO, Vec2::new(image.get_width() as f32, image.get_height() as f32))
+}
+
 impl ImageStack {
     pub fn new(image: DynamicImage) -> Self {
         let mut images = VecDeque::new();
@@ -295,6 +2632,7 @@ impl ImageStack {
             redo_images: VecDeque::new(),
             crop_images,
             tmp_image: Image::new(image.clone(), 0),
+            tmp_changed: None,
             final_image: Image::new(image.clone(), 0),
         }
     }
@@ -307,10 +2645,64 @@ impl ImageStack {
         self.crop_images[index].clone()
     }
 
+    /// What the eraser restores on the active layer of the last stacked image: the clean capture, or nothing for the other layers
+    pub fn get_clean_layer(&self) -> DynamicImage {
+        let last = self.get_last_image_as_ref();
+        let crop_image = self.get_crop_image(last.get_crop_index());
+        if last.layers[last.active_layer].kind == LayerKind::Capture {
+            crop_image
+        } else {
+            DynamicImage::new_rgba8(crop_image.width(), crop_image.height())
+        }
+    }
+
     pub fn get_crop_images_len(&self) -> usize {
         self.crop_images.len()
     }
 
+    /// Remove the uniform or transparent borders of the last stacked image. Returns false if there was nothing to trim
+    pub fn auto_trim(&mut self) -> bool {
+        match find_trim_area(&self.get_last_image().get_composite(), TRIM_TOLERANCE) {
+            Some((x, y, width, height)) => {
+                self.crop(x, y, width, height);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Crop the last stacked image to the window frame surrounding [point]. Returns false if no frame was detected
+    pub fn smart_crop(&mut self, point: (u32, u32)) -> bool {
+        match find_window_frame(&self.get_last_image().get_composite(), point) {
+            Some((x, y, width, height)) => {
+                self.crop(x, y, width, height);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Apply a geometric transformation to all the layers of the last stacked image, stacking the result.
+    /// The clean image used by the eraser gets the same transformation, so that the two stay aligned
+    pub fn transform(&mut self, transform: Transform) {
+        let curr_img = self.get_last_image();
+        let crop_image = transform.apply(&self.crop_images[curr_img.get_crop_index()]);
+        let img = curr_img.map_layers(self.get_crop_images_len(), |layer| transform.apply(layer));
+        self.stack_image(img.clone());
+        self.set_tmp_image(img);
+        self.push_crop_image(crop_image);
+    }
+
+    /// Crop all the layers of the last stacked image to the given area, stacking the result and the matching clean image for the eraser
+    pub fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let curr_img = self.get_last_image();
+        let crop_image = self.get_crop_image(curr_img.get_crop_index()).crop_imm(x, y, width, height);
+        let img = curr_img.map_layers(self.get_crop_images_len(), |layer| layer.crop_imm(x, y, width, height));
+        self.stack_image(img.clone());
+        self.set_tmp_image(img);
+        self.push_crop_image(crop_image);
+    }
+
     /// Push a new image to the redo_images stack
     pub fn push_redo_image(&mut self, image: Image) {
         self.redo_images.push_front(image.into());
@@ -360,6 +2752,14 @@ impl ImageStack {
         }
     }
 
+    /// Guides of the last stacked image, found only the first time they are needed
+    pub fn get_last_guides(&self) -> Guides {
+        match self.images.front() {
+            Some(img) => (**img.guides.get_or_init(|| Rc::new(Guides::from_image(&(*img).clone().into())))).clone(),
+            None => Guides::from_image(&self.final_image),
+        }
+    }
+
     pub fn get_last_image_as_ref (&self) -> &CompressedImage {
         self.images.front().unwrap()
     }
@@ -377,7 +2777,21 @@ impl ImageStack {
     }
 
     pub fn set_tmp_image(&mut self, image: Image) {
-        self.tmp_image = image.clone();
+        self.tmp_changed = Some(whole_area(&image));
+        self.tmp_image = image;
+    }
+
+    /// Record that [area] of the tmp image was changed in place
+    pub fn mark_tmp_changed(&mut self, area: Rect) {
+        self.tmp_changed = Some(match self.tmp_changed {
+            Some(changed) => changed.union(area),
+            None => area,
+        });
+    }
+
+    /// Area of the tmp image changed since the last call
+    pub fn take_tmp_changed(&mut self) -> Option<Rect> {
+        self.tmp_changed.take()
     }
 
     /// Stack an image in the image stack
@@ -391,6 +2805,7 @@ impl ImageStack {
         self.images.clear();
         self.images.push_front(img.clone());
         self.tmp_image = img.clone().into();
+        self.tmp_changed = Some(whole_area(&self.tmp_image));
         self.final_image = self.tmp_image.clone();
     }
     /// Clear the image stack
@@ -403,10 +2818,10 @@ impl ImageStack {
         // Reset all the crop_images and the crop_index for the saved image
         self.crop_images.clear();
         self.final_image = self.get_last_image();
-        self.tmp_image = self.get_last_image();
+        self.set_tmp_image(self.get_last_image());
         self.final_image.crop_index = 0;
         self.tmp_image.crop_index = 0;
-        self.crop_images.push(self.final_image.get_image());
+        self.crop_images.push(self.final_image.get_capture_image());
         self.images.clear();
         self.redo_images.clear();
         self.stack_image(self.final_image.clone());
@@ -417,10 +2832,10 @@ impl ImageStack {
         // Reset all the crop_images and the crop_index for the saved image
         self.crop_images.clear();
         self.final_image = self.get_first_image();
-        self.tmp_image = self.get_first_image();
+        self.set_tmp_image(self.get_first_image());
         self.final_image.crop_index = 0;
         self.tmp_image.crop_index = 0;
-        self.crop_images.push(self.final_image.get_image());
+        self.crop_images.push(self.final_image.get_capture_image());
         self.images.clear();
         self.redo_images.clear();
         self.stack_image(self.final_image.clone());
@@ -431,6 +2846,10 @@ impl ImageStack {
         self.final_image.clone()
     }
 
+    pub fn get_final_image_as_ref(&self) -> &Image {
+        &self.final_image
+    }
+
     pub fn _set_final_image(&mut self, image: Image) {
         self.final_image = image;
     }
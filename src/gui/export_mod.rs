@@ -0,0 +1,290 @@
+use std::path::PathBuf;
+use std::fmt::Display;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageError, Luma, Pixel, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use rusttype::Font;
+use serde::{Deserialize, Serialize};
+use super::image_proc_extra_mod::render_text_image;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum FrameBackground {
+    Solid,
+    Transparent,
+    Gradient,
+}
+
+impl Display for FrameBackground {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FrameBackground::Solid => "Solid",
+            FrameBackground::Transparent => "Transparent",
+            FrameBackground::Gradient => "Gradient",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Decoration added around the final image when exporting: margin, background, rounded corners, border and drop shadow
+#[derive(Clone, PartialEq)]
+pub struct FrameSettings {
+    pub enabled: bool,
+    pub margin: u32,
+    pub background: FrameBackground,
+    pub background_color: [u8; 4],
+    //End color of the vertical gradient, starting from [background_color]
+    pub gradient_color: [u8; 4],
+    pub corner_radius: u32,
+    pub border_width: u32,
+    pub border_color: [u8; 4],
+    pub shadow_blur: u32,
+    pub shadow_offset: (i32, i32),
+    pub shadow_color: [u8; 4],
+}
+
+impl FrameSettings {
+    pub fn new() -> Self {
+        FrameSettings {
+            enabled: false,
+            margin: 40,
+            background: FrameBackground::Solid,
+            background_color: [255, 255, 255, 255],
+            gradient_color: [160, 190, 240, 255],
+            corner_radius: 8,
+            border_width: 0,
+            border_color: [60, 60, 60, 255],
+            shadow_blur: 12,
+            shadow_offset: (0, 6),
+            shadow_color: [0, 0, 0, 110],
+        }
+    }
+}
+
+/// Portion (0 to 1) of the pixel (x, y) covered by the rectangle at (left, top) of the given size with rounded corners
+fn rounded_rect_coverage(x: f32, y: f32, left: f32, top: f32, width: f32, height: f32, radius: f32) -> f32 {
+    //Work on the center of the pixel, relative to the rectangle
+    let (px, py) = (x + 0.5 - left, y + 0.5 - top);
+    if px < 0. || py < 0. || px > width || py > height {
+        return 0.;
+    }
+    let radius = radius.min(width / 2.).min(height / 2.);
+    let cx = px.clamp(radius, width - radius);
+    let cy = py.clamp(radius, height - radius);
+    if cx == px || cy == py {
+        //Not in a corner: only the straight edges can be partially covered
+        return (px.min(width - px).min(py).min(height - py) + 0.5).clamp(0., 1.);
+    }
+    let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+    (radius - distance + 0.5).clamp(0., 1.)
+}
+
+/// Blend [color] over [pixel] with its alpha multiplied by [coverage]
+fn blend_with_coverage(pixel: &mut Rgba<u8>, color: [u8; 4], coverage: f32) {
+    let alpha = (color[3] as f32 * coverage).round() as u8;
+    if alpha > 0 {
+        pixel.blend(&Rgba([color[0], color[1], color[2], alpha]));
+    }
+}
+
+/// Put [img] on a larger canvas, decorated following the given [FrameSettings]
+pub fn apply_frame(img: &DynamicImage, settings: &FrameSettings) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let border = settings.border_width;
+    let margin = settings.margin;
+    let (canvas_width, canvas_height) = (width + 2 * (margin + border), height + 2 * (margin + border));
+
+    //Background
+    let mut canvas = RgbaImage::from_fn(canvas_width, canvas_height, |_, y| match settings.background {
+        FrameBackground::Solid => Rgba(settings.background_color),
+        FrameBackground::Transparent => Rgba([0, 0, 0, 0]),
+        FrameBackground::Gradient => {
+            let t = y as f32 / (canvas_height.max(2) - 1) as f32;
+            let mut color = settings.background_color;
+            for (channel, end) in color.iter_mut().zip(settings.gradient_color) {
+                *channel = (*channel as f32 * (1. - t) + end as f32 * t).round() as u8;
+            }
+            Rgba(color)
+        }
+    });
+
+    //The box made of the image and its border
+    let (box_left, box_top) = (margin as f32, margin as f32);
+    let (box_width, box_height) = ((width + 2 * border) as f32, (height + 2 * border) as f32);
+    let radius = settings.corner_radius as f32;
+    let outer_radius = if radius > 0. { radius + border as f32 } else { 0. };
+
+    //Drop shadow: the shape of the box, shifted and blurred
+    if settings.shadow_color[3] > 0 && (settings.shadow_blur > 0 || settings.shadow_offset != (0, 0)) {
+        let (dx, dy) = (settings.shadow_offset.0 as f32, settings.shadow_offset.1 as f32);
+        let mut mask = GrayImage::from_fn(canvas_width, canvas_height, |x, y| {
+            let coverage = rounded_rect_coverage(x as f32, y as f32, box_left + dx, box_top + dy, box_width, box_height, outer_radius);
+            Luma([(coverage * 255.).round() as u8])
+        });
+        if settings.shadow_blur > 0 {
+            mask = imageproc::filter::gaussian_blur_f32(&mask, settings.shadow_blur as f32 / 2.);
+        }
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            blend_with_coverage(pixel, settings.shadow_color, mask.get_pixel(x, y)[0] as f32 / 255.);
+        }
+    }
+
+    //Border and image, both with rounded corners
+    let (img_left, img_top) = ((margin + border) as f32, (margin + border) as f32);
+    for y in margin..margin + height + 2 * border {
+        for x in margin..margin + width + 2 * border {
+            let outer = rounded_rect_coverage(x as f32, y as f32, box_left, box_top, box_width, box_height, outer_radius);
+            if outer <= 0. {
+                continue;
+            }
+            let inner = rounded_rect_coverage(x as f32, y as f32, img_left, img_top, width as f32, height as f32, radius);
+            let pixel = canvas.get_pixel_mut(x, y);
+            if border > 0 {
+                blend_with_coverage(pixel, settings.border_color, outer * (1. - inner));
+            }
+            if inner > 0. {
+                let img_pixel = img.get_pixel(x - margin - border, y - margin - border);
+                blend_with_coverage(pixel, img_pixel.0, inner);
+            }
+        }
+    }
+    DynamicImage::ImageRgba8(canvas)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum WatermarkKind {
+    Text,
+    Logo,
+}
+
+impl Display for WatermarkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WatermarkKind::Text => "Text",
+            WatermarkKind::Logo => "Logo",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl Display for WatermarkPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WatermarkPosition::TopLeft => "Top left",
+            WatermarkPosition::TopRight => "Top right",
+            WatermarkPosition::BottomLeft => "Bottom left",
+            WatermarkPosition::BottomRight => "Bottom right",
+            WatermarkPosition::Center => "Center",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Watermark profile: text or logo drawn on the image when exporting, once in a corner or tiled over the whole image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatermarkSettings {
+    pub name: String,
+    pub kind: WatermarkKind,
+    pub text: String,
+    pub font: String,
+    pub font_size: f32,
+    pub color: [u8; 4],
+    pub logo_path: Option<PathBuf>,
+    //Width of the logo, as a fraction of the width of the image
+    pub logo_scale: f32,
+    pub position: WatermarkPosition,
+    pub margin: u32,
+    pub opacity: f32,
+    pub tiled: bool,
+    //Space between the repeated watermarks, when tiled
+    pub spacing: u32,
+}
+
+impl WatermarkSettings {
+    pub fn new(name: &str) -> Self {
+        WatermarkSettings {
+            name: name.to_string(),
+            kind: WatermarkKind::Text,
+            text: "RustShot".to_string(),
+            font: String::new(),
+            font_size: 32.,
+            color: [255, 255, 255, 255],
+            logo_path: None,
+            logo_scale: 0.15,
+            position: WatermarkPosition::BottomRight,
+            margin: 16,
+            opacity: 0.5,
+            tiled: false,
+            spacing: 80,
+        }
+    }
+
+    /// The watermark alone, before it is placed on an image [width] pixels wide. None if there is nothing to draw, an error if the logo can't be loaded
+    fn mark(&self, width: u32, font: Option<&Font>) -> Result<Option<RgbaImage>, ImageError> {
+        let mark = match self.kind {
+            WatermarkKind::Text => match font.and_then(|font| render_text_image(font, &self.text, self.font_size, self.color)) {
+                Some(text) => text.to_rgba8(),
+                None => return Ok(None),
+            },
+            WatermarkKind::Logo => {
+                let logo = match &self.logo_path {
+                    Some(path) => image::open(path)?,
+                    None => return Ok(None),
+                };
+                let logo_width = (width as f32 * self.logo_scale).round().max(1.) as u32;
+                let logo_height = ((logo.height() as f32 * logo_width as f32 / logo.width().max(1) as f32).round() as u32).max(1);
+                logo.resize_exact(logo_width, logo_height, FilterType::Triangle).to_rgba8()
+            }
+        };
+        let mut mark = mark;
+        for pixel in mark.pixels_mut() {
+            pixel.0[3] = (pixel.0[3] as f32 * self.opacity.clamp(0., 1.)).round() as u8;
+        }
+        Ok(Some(mark))
+    }
+}
+
+/// Draw the watermark described by [settings] on [img]. Text watermarks are drawn with [font]. An error if the logo can't be loaded
+pub fn apply_watermark(img: &DynamicImage, settings: &WatermarkSettings, font: Option<&Font>) -> Result<DynamicImage, ImageError> {
+    let (width, height) = img.dimensions();
+    let mark = match settings.mark(width, font)? {
+        Some(mark) => mark,
+        None => return Ok(img.clone()),
+    };
+    let (mark_width, mark_height) = (mark.width() as i64, mark.height() as i64);
+    let mut canvas = img.to_rgba8();
+    if settings.tiled {
+        //Every other row is shifted by half a step, like bricks
+        let (step_x, step_y) = (mark_width + settings.spacing as i64, mark_height + settings.spacing as i64);
+        let mut row = 0;
+        let mut y = -(step_y / 2);
+        while y < height as i64 {
+            let mut x = if row % 2 == 0 { 0 } else { -(step_x / 2) };
+            while x < width as i64 {
+                image::imageops::overlay(&mut canvas, &mark, x, y);
+                x += step_x;
+            }
+            y += step_y;
+            row += 1;
+        }
+    } else {
+        let margin = settings.margin as i64;
+        let (right, bottom) = (width as i64 - mark_width - margin, height as i64 - mark_height - margin);
+        let (x, y) = match settings.position {
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (right, margin),
+            WatermarkPosition::BottomLeft => (margin, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center => ((width as i64 - mark_width) / 2, (height as i64 - mark_height) / 2),
+        };
+        image::imageops::overlay(&mut canvas, &mark, x, y);
+    }
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
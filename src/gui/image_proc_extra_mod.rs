@@ -1,5 +1,5 @@
 use eframe::egui::{Pos2, Vec2, Rect};
-use image::{DynamicImage, GenericImageView, Pixel};
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Pixel, RgbaImage};
 use imageproc::drawing;
 use imageproc::drawing::{BresenhamLineIter, Canvas, draw_filled_circle_mut};
 use rusttype::{point, Font, Scale};
@@ -18,15 +18,182 @@ pub fn measure_line(font: &Font, text: &str, scale: Scale) -> (f32, f32) {
     (width, height)
 }
 
-pub fn draw_arrow (img: &mut DynamicImage, start:(f32, f32), end:(f32, f32), t: usize, color: [u8; 4]) {
-    draw_thick_line(img, start, end, t, color);
-    let v = Vec2::new(end.0 - start.0, end.1 - start.1).normalized();
-    let perpendicular = v.rot90().normalized();
-    let t2 = (t as f32)/3. + 1.;
-    let p1 = (end.0 - v.x * 10.0 * t2  - perpendicular.x * 10.0 * t2 , end.1 - v.y * 10.0 * t2 - perpendicular.y * 10.0 * t2);
-    let p2 = (end.0 - v.x * 10.0 * t2  + perpendicular.x * 10.0 * t2 , end.1 - v.y * 10.0 * t2 + perpendicular.y * 10.0 * t2);
-    draw_thick_line(img, p1, end, t, color);
-    draw_thick_line(img, p2, end, t, color);
+/// Slant applied to synthesized italic glyphs (horizontal shift per pixel of height)
+const FAKE_ITALIC_SHEAR: f32 = 0.2;
+
+/// Horizontal offset used to thicken synthesized bold glyphs
+pub fn fake_bold_offset(scale: Scale) -> f32 {
+    (scale.y / 24.).max(1.).round()
+}
+
+/// Font a text is drawn with, at the size given by [scale].
+/// [fake_bold] and [fake_italic] synthesize the styles for fonts that don't have a bold/italic variant
+#[derive(Clone, Copy)]
+pub struct StyledFont<'a> {
+    pub font: &'a Font<'a>,
+    pub scale: Scale,
+    pub fake_bold: bool,
+    pub fake_italic: bool,
+}
+
+/// Draw a line of text with its top left corner at (x, y), blending the glyphs coverage with [color]
+pub fn draw_styled_text_mut(canvas: &mut DynamicImage, color: [u8; 4], (x, y): (f32, f32), style: StyledFont, text: &str) {
+    let StyledFont { font, scale, fake_bold, fake_italic } = style;
+    let (width, height) = GenericImageView::dimensions(canvas);
+    let baseline = y + font.v_metrics(scale).ascent;
+    let passes = if fake_bold { fake_bold_offset(scale) as i32 + 1 } else { 1 };
+    for glyph in font.layout(text, scale, point(x, baseline)) {
+        if let Some(bb) = glyph.pixel_bounding_box() {
+            glyph.draw(|gx, gy, v| {
+                let py = bb.min.y + gy as i32;
+                let shear = if fake_italic { ((baseline - py as f32) * FAKE_ITALIC_SHEAR).round() as i32 } else { 0 };
+                for pass in 0..passes {
+                    let px = bb.min.x + gx as i32 + shear + pass;
+                    if px >= 0 && py >= 0 && px < width as i32 && py < height as i32 {
+                        let alpha = (color[3] as f32 * v).round() as u8;
+                        let mut pixel = GenericImageView::get_pixel(canvas, px as u32, py as u32);
+                        pixel.blend(&image::Rgba([color[0], color[1], color[2], alpha]));
+                        canvas.draw_pixel(px as u32, py as u32, pixel);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Draw [text] around itself with [color], at every offset within [radius], so that it stands out on busy backgrounds
+pub fn draw_text_outline_mut(canvas: &mut DynamicImage, color: [u8; 4], (x, y): (f32, f32), style: StyledFont, text: &str, radius: u32) {
+    let r = radius as i32;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            if (dx != 0 || dy != 0) && dx * dx + dy * dy <= r * r {
+                draw_styled_text_mut(canvas, color, (x + dx as f32, y + dy as f32), style, text);
+            }
+        }
+    }
+}
+
+/// Points along the quadratic Bézier curve from [start] to [end] bent towards [control], close enough to be joined by straight segments
+pub fn quadratic_bezier_points(start: (f32, f32), control: (f32, f32), end: (f32, f32)) -> Vec<(f32, f32)> {
+    let length = |a: (f32, f32), b: (f32, f32)| ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    let steps = ((length(start, control) + length(control, end)) / 3.).clamp(8., 400.) as usize;
+    (0..=steps).map(|i| {
+        let t = i as f32 / steps as f32;
+        let (a, b, c) = ((1. - t) * (1. - t), 2. * (1. - t) * t, t * t);
+        (a * start.0 + b * control.0 + c * end.0, a * start.1 + b * control.1 + c * end.1)
+    }).collect()
+}
+
+/// Render [text] on a transparent image just large enough to contain it, [size] pixels high. None if nothing is drawn
+pub fn render_text_image(font: &Font, text: &str, size: f32, color: [u8; 4]) -> Option<DynamicImage> {
+    let scale = Scale::uniform(size);
+    let v_metrics = font.v_metrics(scale);
+    let glyphs: Vec<_> = font.layout(text, scale, point(0., v_metrics.ascent)).collect();
+    let bounds = glyphs.iter().filter_map(|g| g.pixel_bounding_box()).reduce(|a, b| {
+        rusttype::Rect { min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)), max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)) }
+    })?;
+    let (width, height) = ((bounds.max.x - bounds.min.x) as u32, (bounds.max.y - bounds.min.y) as u32);
+    let mut img = RgbaImage::new(width, height);
+    for glyph in &glyphs {
+        if let Some(bb) = glyph.pixel_bounding_box() { glyph.draw(|x, y, coverage| {
+            let (px, py) = ((bb.min.x - bounds.min.x) as u32 + x, (bb.min.y - bounds.min.y) as u32 + y);
+            if px < width && py < height {
+                let alpha = (color[3] as f32 * coverage.min(1.)).round() as u8;
+                let pixel = img.get_pixel_mut(px, py);
+                if alpha > pixel.0[3] {
+                    *pixel = image::Rgba([color[0], color[1], color[2], alpha]);
+                }
+            }
+        }) }
+    }
+    Some(DynamicImage::ImageRgba8(img))
+}
+
+/// Draw [text] with its top left corner in [pos] on a [background] box, like a tooltip. Returns the area covered, as (min, max)
+pub fn draw_label_mut(canvas: &mut DynamicImage, font: &Font, text: &str, pos: (f32, f32), size: f32, color: [u8; 4], background: [u8; 4]) -> Option<((f32, f32), (f32, f32))> {
+    let label = render_text_image(font, text, size, color)?;
+    let padding = (size / 4.).round();
+    let max = (pos.0 + label.width() as f32 + 2. * padding, pos.1 + label.height() as f32 + 2. * padding);
+    draw_blended_rect_mut(canvas, pos, max, background);
+    image::imageops::overlay(canvas, &label, (pos.0 + padding) as i64, (pos.1 + padding) as i64);
+    Some((pos, max))
+}
+
+/// Points along the Catmull-Rom spline segment from [p1] to [p2], with [p0] and [p3] the points before and after them,
+/// so that a freehand stroke passes smoothly through all its samples
+pub fn catmull_rom_points(p0: (f32, f32), p1: (f32, f32), p2: (f32, f32), p3: (f32, f32)) -> Vec<(f32, f32)> {
+    let length = ((p2.0 - p1.0).powi(2) + (p2.1 - p1.1).powi(2)).sqrt();
+    let steps = (length / 2.).clamp(1., 200.) as usize;
+    let spline = |a: f32, b: f32, c: f32, d: f32, t: f32| {
+        0.5 * (2. * b + (c - a) * t + (2. * a - 5. * b + 4. * c - d) * t * t + (3. * b - a - 3. * c + d) * t * t * t)
+    };
+    (0..=steps).map(|i| {
+        let t = i as f32 / steps as f32;
+        (spline(p0.0, p1.0, p2.0, p3.0, t), spline(p0.1, p1.1, p2.1, p3.1, t))
+    }).collect()
+}
+
+/// Draw a thick line through [points]. With [dash] = Some((on, off)) the stroke is interrupted: [on] pixels drawn and [off] skipped, along the whole line.
+/// A dash of length 0 is a single dot
+pub fn draw_polyline_mut(img: &mut DynamicImage, points: &[(f32, f32)], t: usize, color: [u8; 4], dash: Option<(f32, f32)>) {
+    let (on, off) = match dash {
+        Some(dash) => dash,
+        None => {
+            for pair in points.windows(2) {
+                draw_thick_line(img, pair[0], pair[1], t, color);
+            }
+            return;
+        }
+    };
+    //Current phase of the pattern and the distance left before it changes
+    let mut drawing = true;
+    let mut left = on;
+    for pair in points.windows(2) {
+        let (mut from, to) = (pair[0], pair[1]);
+        let mut length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        if length <= 0. {
+            continue;
+        }
+        let direction = ((to.0 - from.0) / length, (to.1 - from.1) / length);
+        loop {
+            let step = left.min(length);
+            let next = (from.0 + direction.0 * step, from.1 + direction.1 * step);
+            if drawing {
+                draw_thick_line(img, from, next, t, color);
+            }
+            length -= step;
+            left -= step;
+            from = next;
+            if left <= 0. {
+                drawing = !drawing;
+                left = if drawing { on } else { off };
+            }
+            if length <= 0. {
+                break;
+            }
+        }
+    }
+}
+
+/// Draw the head of an arrow pointing to [tip] along [direction], [size] pixels long. Filled heads are solid triangles, the others two strokes
+pub fn draw_arrow_head_mut(img: &mut DynamicImage, tip: (f32, f32), direction: Vec2, size: f32, t: usize, color: [u8; 4], filled: bool) {
+    let v = direction.normalized();
+    let perpendicular = v.rot90();
+    let p1 = (tip.0 - v.x * size - perpendicular.x * size, tip.1 - v.y * size - perpendicular.y * size);
+    let p2 = (tip.0 - v.x * size + perpendicular.x * size, tip.1 - v.y * size + perpendicular.y * size);
+    if filled {
+        let polygon = [
+            imageproc::point::Point::new(tip.0.round() as i32, tip.1.round() as i32),
+            imageproc::point::Point::new(p1.0.round() as i32, p1.1.round() as i32),
+            imageproc::point::Point::new(p2.0.round() as i32, p2.1.round() as i32),
+        ];
+        if polygon[0] != polygon[1] && polygon[0] != polygon[2] && polygon[1] != polygon[2] {
+            drawing::draw_polygon_mut(img, &polygon, color.into());
+        }
+    } else {
+        draw_thick_line(img, p1, tip, t, color);
+        draw_thick_line(img, p2, tip, t, color);
+    }
 }
 
 /// Draw a thick line using filled circles.
@@ -82,6 +249,34 @@ pub fn draw_thick_hollow_circle_mut(canvas: &mut DynamicImage, center: (i32, i32
     }
 }
 
+/// Draw the anti-aliased ellipse inscribed in the box [min]-[max]. It is filled if [thickness] is None,
+/// otherwise only its outline is drawn, as wide as the lines of the other tools of the same thickness
+pub fn draw_ellipse_mut(canvas: &mut DynamicImage, min: (f32, f32), max: (f32, f32), thickness: Option<usize>, color: [u8; 4]) {
+    let (width, height) = GenericImageView::dimensions(canvas);
+    let center = ((min.0 + max.0) / 2., (min.1 + max.1) / 2.);
+    let radius = (((max.0 - min.0) / 2.).max(0.5), ((max.1 - min.1) / 2.).max(0.5));
+    let half_width = thickness.map(|t| t as f32 + 0.5);
+    let margin = half_width.unwrap_or(0.) + 1.;
+    let left = ((min.0 - margin).floor().max(0.) as u32).min(width);
+    let top = ((min.1 - margin).floor().max(0.) as u32).min(height);
+    let right = ((max.0 + margin).ceil().max(0.) as u32).min(width);
+    let bottom = ((max.1 + margin).ceil().max(0.) as u32).min(height);
+    for y in top..bottom {
+        for x in left..right {
+            let (px, py) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+            //Approximated signed distance from the ellipse, accurate near its outline where it matters
+            let k0 = ((px / radius.0).powi(2) + (py / radius.1).powi(2)).sqrt();
+            let k1 = ((px / (radius.0 * radius.0)).powi(2) + (py / (radius.1 * radius.1)).powi(2)).sqrt();
+            let distance = if k1 > 0. { k0 * (k0 - 1.) / k1 } else { -radius.0.min(radius.1) };
+            let coverage = match half_width {
+                Some(half_width) => (half_width + 0.5 - distance.abs()).clamp(0., 1.),
+                None => (0.5 - distance).clamp(0., 1.),
+            };
+            blend_pixel_mut(canvas, x, y, color, coverage);
+        }
+    }
+}
+
 pub fn draw_thick_hollow_rect_mut(canvas: &mut DynamicImage, rect: imageproc::rect::Rect, thickness:usize, color: [u8; 4]) {
     let left = rect.left() as f32;
     let right = rect.right() as f32;
@@ -94,69 +289,16 @@ pub fn draw_thick_hollow_rect_mut(canvas: &mut DynamicImage, rect: imageproc::re
     draw_thick_line_with_rect(canvas, (right, top), (right, bottom), thickness * 2, color.into());
 }
 
-pub fn draw_blended_line_segment_mut(original_canvas:&DynamicImage, canvas: &mut DynamicImage, start: (f32, f32), end: (f32, f32), mut color: [u8; 4]) {
+/// Blend [color] over every pixel of the rectangle between [min] and [max], keeping what is below visible
+pub fn draw_blended_rect_mut(canvas: &mut DynamicImage, min: (f32, f32), max: (f32, f32), color: [u8; 4]) {
     let (width, height) = GenericImageView::dimensions(canvas);
-    let in_bounds = |x, y| x >= 0 && x < width as i32 && y >= 0 && y < height as i32;
-
-    let line_iterator = BresenhamLineIter::new(start, end);
-
-    for point in line_iterator {
-        let x = point.0;
-        let y = point.1;
-
-        if in_bounds(x, y) {
-            let mut pixel = GenericImageView::get_pixel(original_canvas, x as u32, y as u32);
-            //Make the alpha channel equals half of the alpha channel of the original pixel
-            color[3] = pixel[3] / 10;
+    let (x0, x1) = (min.0.max(0.) as u32, (max.0.max(0.) as u32).min(width));
+    let (y0, y1) = (min.1.max(0.) as u32, (max.1.max(0.) as u32).min(height));
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let mut pixel = GenericImageView::get_pixel(canvas, x, y);
             pixel.blend(&color.into());
-            canvas.draw_pixel(x as u32, y as u32, pixel);
-        }
-    }
-}
-
-pub fn draw_blended_filled_circle_mut(original_canvas:&DynamicImage, canvas: &mut DynamicImage, center: (i32, i32), radius: i32, color: [u8; 4]) {
-    let mut x = 0i32;
-    let mut y = radius;
-    let mut p = 1 - radius;
-    let x0 = center.0;
-    let y0 = center.1;
-
-    while x <= y {
-        draw_blended_line_segment_mut(
-            original_canvas,
-            canvas,
-            ((x0 - x) as f32, (y0 + y) as f32),
-            ((x0 + x) as f32, (y0 + y) as f32),
-            color,
-        );
-        draw_blended_line_segment_mut(
-            original_canvas,
-            canvas,
-            ((x0 - y) as f32, (y0 + x) as f32),
-            ((x0 + y) as f32, (y0 + x) as f32),
-            color,
-        );
-        draw_blended_line_segment_mut(
-            original_canvas,
-            canvas,
-            ((x0 - x) as f32, (y0 - y) as f32),
-            ((x0 + x) as f32, (y0 - y) as f32),
-            color,
-        );
-        draw_blended_line_segment_mut(
-            original_canvas,
-            canvas,
-            ((x0 - y) as f32, (y0 - x) as f32),
-            ((x0 + y) as f32, (y0 - x) as f32),
-            color,
-        );
-
-        x += 1;
-        if p < 0 {
-            p += 2 * x + 1;
-        } else {
-            y -= 1;
-            p += 2 * (x - y) + 1;
+            canvas.draw_pixel(x, y, pixel);
         }
     }
 }
@@ -224,10 +366,99 @@ pub fn erase_filled_circle_mut(original_canvas:&DynamicImage, canvas: &mut Dynam
     }
 }
 
-pub fn highlight_line(original_img:&DynamicImage, img: &mut DynamicImage, start:(f32, f32), end:(f32, f32), t: usize, color: [u8; 4]) {
-    let segment = bresenham_line(start.0 as usize, start.1 as usize, end.0 as usize, end.1 as usize);
-    for point in segment {
-        draw_blended_filled_circle_mut(original_img,img, (point.0 as i32, point.1 as i32), t as i32, color.into());
+/// Add the round capped segment from [start] to [end], of radius [t], to the coverage [mask] of a highlighter stroke.
+/// Coverage only grows, so the parts of the stroke that overlap are not counted twice.
+/// Returns the area of the mask that may have changed, as (x0, y0, x1, y1) with the end excluded
+pub fn add_stroke_to_mask(mask: &mut GrayImage, start: (f32, f32), end: (f32, f32), t: f32) -> (u32, u32, u32, u32) {
+    add_tapered_stroke_to_mask(mask, start, end, t, t)
+}
+
+/// Same as [add_stroke_to_mask], with the radius changing from [t0] at [start] to [t1] at [end]
+pub fn add_tapered_stroke_to_mask(mask: &mut GrayImage, start: (f32, f32), end: (f32, f32), t0: f32, t1: f32) -> (u32, u32, u32, u32) {
+    let (width, height) = mask.dimensions();
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let len = dx * dx + dy * dy;
+    let reach = t0.max(t1) + 1.;
+    let x0 = (start.0.min(end.0) - reach).floor().max(0.) as u32;
+    let y0 = (start.1.min(end.1) - reach).floor().max(0.) as u32;
+    let x1 = ((start.0.max(end.0) + reach).ceil().max(0.) as u32).min(width);
+    let y1 = ((start.1.max(end.1) + reach).ceil().max(0.) as u32).min(height);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+            let along = if len > 0. { (((p.0 - start.0) * dx + (p.1 - start.1) * dy) / len).clamp(0., 1.) } else { 0. };
+            let t = t0 + (t1 - t0) * along;
+            let distance = ((p.0 - start.0 - along * dx).powi(2) + (p.1 - start.1 - along * dy).powi(2)).sqrt();
+            let coverage = ((t + 0.5 - distance).clamp(0., 1.) * 255.).round() as u8;
+            let pixel = mask.get_pixel_mut(x, y);
+            if coverage > pixel.0[0] {
+                *pixel = Luma([coverage]);
+            }
+        }
+    }
+    (x0, y0, x1, y1)
+}
+
+/// Draw the stroke described by [mask] on the [area] of [canvas], blending [color] over [original_canvas]
+pub fn composite_stroke_mut(original_canvas: &DynamicImage, canvas: &mut DynamicImage, mask: &GrayImage, area: (u32, u32, u32, u32), color: [u8; 4]) {
+    let (x0, y0, x1, y1) = area;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let coverage = mask.get_pixel(x, y).0[0];
+            if coverage == 0 {
+                continue;
+            }
+            canvas.draw_pixel(x, y, GenericImageView::get_pixel(original_canvas, x, y));
+            blend_pixel_mut(canvas, x, y, color, coverage as f32 / 255.);
+        }
+    }
+}
+
+/// Draw the highlighter stroke described by [mask] on the [area] of [canvas], starting from [original_canvas].
+/// The color multiplies what is below, like a marker on paper, with the given [opacity] and the alpha of [color]
+pub fn composite_highlight_mut(original_canvas: &DynamicImage, canvas: &mut DynamicImage, mask: &GrayImage, area: (u32, u32, u32, u32), color: [u8; 4], opacity: f32) {
+    let strength = opacity.clamp(0., 1.) * color[3] as f32 / 255.;
+    let (x0, y0, x1, y1) = area;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let coverage = mask.get_pixel(x, y).0[0];
+            if coverage == 0 {
+                continue;
+            }
+            let alpha = strength * coverage as f32 / 255.;
+            let mut pixel = GenericImageView::get_pixel(original_canvas, x, y);
+            //Where a layer is transparent there is nothing to multiply, the color is laid down as it is
+            let alpha_below = pixel.0[3] as f32 / 255.;
+            let alpha_result = alpha + alpha_below * (1. - alpha);
+            for (channel, tint) in pixel.0.iter_mut().zip(color).take(3) {
+                let below = *channel as f32;
+                let marker = tint as f32 + (below * tint as f32 / 255. - tint as f32) * alpha_below;
+                *channel = ((marker * alpha + below * alpha_below * (1. - alpha)) / alpha_result).round() as u8;
+            }
+            pixel.0[3] = (alpha_result * 255.).round() as u8;
+            canvas.draw_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Draw [layer] over [canvas], with its alpha scaled by [opacity]. [blend] mixes a channel of the canvas with the one of the layer,
+/// both between 0 and 1, where the two are opaque
+pub fn blend_layer_mut(canvas: &mut RgbaImage, layer: &RgbaImage, opacity: f32, blend: impl Fn(f32, f32) -> f32) {
+    for (below, above) in canvas.pixels_mut().zip(layer.pixels()) {
+        let alpha_above = above.0[3] as f32 / 255. * opacity;
+        if alpha_above == 0. {
+            continue;
+        }
+        let alpha_below = below.0[3] as f32 / 255.;
+        let alpha = alpha_above + alpha_below * (1. - alpha_above);
+        for c in 0..3 {
+            let (cb, cs) = (below.0[c] as f32 / 255., above.0[c] as f32 / 255.);
+            //Where the canvas is transparent the layer keeps its own color
+            let mixed = (1. - alpha_below) * cs + alpha_below * blend(cb, cs);
+            let color = (alpha_above * mixed + (1. - alpha_above) * alpha_below * cb) / alpha;
+            below.0[c] = (color * 255.).round().clamp(0., 255.) as u8;
+        }
+        below.0[3] = (alpha * 255.).round() as u8;
     }
 }
 
@@ -266,9 +497,414 @@ pub fn bresenham_line(x0: usize, y0: usize, x1: usize, y1: usize) -> Vec<(usize,
     points
 }
 
+/// Radius of the corners of a callout box
+const CALLOUT_RADIUS: f32 = 10.;
+
+/// Signed distance from [p] to the rectangle [min]-[max] with rounded corners, negative inside
+fn rounded_rect_distance(p: (f32, f32), min: (f32, f32), max: (f32, f32), radius: f32) -> f32 {
+    let half = ((max.0 - min.0) / 2., (max.1 - min.1) / 2.);
+    let radius = radius.min(half.0).min(half.1).max(0.);
+    let q = ((p.0 - (min.0 + half.0)).abs() - half.0 + radius, (p.1 - (min.1 + half.1)).abs() - half.1 + radius);
+    let outside = (q.0.max(0.).powi(2) + q.1.max(0.).powi(2)).sqrt();
+    outside + q.0.max(q.1).min(0.) - radius
+}
+
+/// Distance from [p] to the segment [a]-[b]
+pub fn segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = dx * dx + dy * dy;
+    let t = if len > 0. { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len).clamp(0., 1.) } else { 0. };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
+
+/// Distance between the segments [a0]-[a1] and [b0]-[b1]
+pub fn segments_distance(a0: (f32, f32), a1: (f32, f32), b0: (f32, f32), b1: (f32, f32)) -> f32 {
+    let cross = |o: (f32, f32), p: (f32, f32), q: (f32, f32)| (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0);
+    //Segments crossing each other have the ends of each one on opposite sides of the other
+    if cross(a0, a1, b0) * cross(a0, a1, b1) < 0. && cross(b0, b1, a0) * cross(b0, b1, a1) < 0. {
+        return 0.;
+    }
+    segment_distance(a0, b0, b1).min(segment_distance(a1, b0, b1)).min(segment_distance(b0, a0, a1)).min(segment_distance(b1, a0, a1))
+}
+
+/// Signed distance from [p] to the triangle [a], [b], [c], negative inside
+fn triangle_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> f32 {
+    let distance = segment_distance(p, a, b).min(segment_distance(p, b, c)).min(segment_distance(p, c, a));
+    let side = |u: (f32, f32), v: (f32, f32)| (v.0 - u.0) * (p.1 - u.1) - (v.1 - u.1) * (p.0 - u.0);
+    let (s1, s2, s3) = (side(a, b), side(b, c), side(c, a));
+    let inside = (s1 >= 0. && s2 >= 0. && s3 >= 0.) || (s1 <= 0. && s2 <= 0. && s3 <= 0.);
+    if inside { -distance } else { distance }
+}
+
+/// Draw a speech bubble: a rounded box from [min] to [max] and a tail pointing to [tail], filled with [fill] and with a [thickness] wide border of [color].
+/// Box and tail are merged in a single shape, so the border goes around both without a seam
+pub fn draw_callout_mut(canvas: &mut DynamicImage, min: (f32, f32), max: (f32, f32), tail: (f32, f32), thickness: usize, color: [u8; 4], fill: [u8; 4]) {
+    let (width, height) = GenericImageView::dimensions(canvas);
+    let center = ((min.0 + max.0) / 2., (min.1 + max.1) / 2.);
+    //The tail is a wedge from the center of the box, so that its base is always hidden inside it
+    let (dx, dy) = (tail.0 - center.0, tail.1 - center.1);
+    let length = (dx * dx + dy * dy).sqrt().max(1.);
+    let base = ((max.0 - min.0).min(max.1 - min.1) / 4.).clamp(4., 24.);
+    let (px, py) = (-dy / length * base, dx / length * base);
+    let (b1, b2) = ((center.0 + px, center.1 + py), (center.0 - px, center.1 - py));
+    let t = thickness as f32;
+    let left = (min.0.min(tail.0) - 1.).floor().max(0.) as u32;
+    let top = (min.1.min(tail.1) - 1.).floor().max(0.) as u32;
+    let right = ((max.0.max(tail.0) + 1.).ceil().max(0.) as u32).min(width);
+    let bottom = ((max.1.max(tail.1) + 1.).ceil().max(0.) as u32).min(height);
+    for y in top..bottom {
+        for x in left..right {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+            let distance = rounded_rect_distance(p, min, max, CALLOUT_RADIUS).min(triangle_distance(p, tail, b1, b2));
+            //Antialiased coverage of the whole shape and of the area inside the border
+            let outer = (0.5 - distance).clamp(0., 1.);
+            let inner = (0.5 - distance - t).clamp(0., 1.);
+            if outer <= 0. {
+                continue;
+            }
+            let mut pixel = GenericImageView::get_pixel(canvas, x, y);
+            if inner > 0. {
+                pixel.blend(&image::Rgba([fill[0], fill[1], fill[2], (fill[3] as f32 * inner).round() as u8]));
+            }
+            if outer > inner {
+                pixel.blend(&image::Rgba([color[0], color[1], color[2], (color[3] as f32 * (outer - inner)).round() as u8]));
+            }
+            canvas.draw_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Blend [color] over the pixel (x, y) of [canvas], with its alpha multiplied by [coverage]
+fn blend_pixel_mut(canvas: &mut DynamicImage, x: u32, y: u32, color: [u8; 4], coverage: f32) {
+    let alpha = (color[3] as f32 * coverage).round() as u8;
+    if alpha > 0 {
+        let mut pixel = GenericImageView::get_pixel(canvas, x, y);
+        pixel.blend(&image::Rgba([color[0], color[1], color[2], alpha]));
+        canvas.draw_pixel(x, y, pixel);
+    }
+}
+
+/// Point where the ray from the center of a shape towards [direction] leaves it
+fn shape_exit_point(center: (f32, f32), half: (f32, f32), circle: bool, direction: (f32, f32)) -> (f32, f32) {
+    let length = (direction.0 * direction.0 + direction.1 * direction.1).sqrt().max(f32::EPSILON);
+    let (dx, dy) = (direction.0 / length, direction.1 / length);
+    let t = if circle {
+        half.0
+    } else {
+        (half.0 / dx.abs().max(f32::EPSILON)).min(half.1 / dy.abs().max(f32::EPSILON))
+    };
+    (center.0 + dx * t, center.1 + dy * t)
+}
+
+/// Area left lit by the spotlight: the rectangle [min]-[max], or the ellipse inside it if [ellipse] is true
+#[derive(Clone, Copy)]
+pub struct SpotlightArea {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+    pub ellipse: bool,
+}
+
+/// Dim and desaturate [canvas] outside the [areas]. [dim] and [desaturate] go from 0 (unchanged) to 1,
+/// the change fades in over [feather] pixels around the areas. Only the pixels in [region], given as (left, top, right, bottom), are changed
+pub fn draw_spotlight_mut(canvas: &mut DynamicImage, areas: &[SpotlightArea], dim: f32, desaturate: f32, feather: f32, region: (u32, u32, u32, u32)) {
+    let (width, height) = GenericImageView::dimensions(canvas);
+    let feather = feather.max(0.5);
+    for y in region.1..region.3.min(height) {
+        for x in region.0..region.2.min(width) {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+            //How much the pixel is lit by the nearest area
+            let lit = areas.iter().map(|&SpotlightArea { min, max, ellipse }| {
+                let distance = if ellipse {
+                    let center = ((min.0 + max.0) / 2., (min.1 + max.1) / 2.);
+                    let radius = (((max.0 - min.0) / 2.).max(0.5), ((max.1 - min.1) / 2.).max(0.5));
+                    let k = (((p.0 - center.0) / radius.0).powi(2) + ((p.1 - center.1) / radius.1).powi(2)).sqrt();
+                    (k - 1.) * radius.0.min(radius.1)
+                } else {
+                    rounded_rect_distance(p, min, max, 0.)
+                };
+                (0.5 - distance / feather).clamp(0., 1.)
+            }).fold(0., f32::max);
+            if lit >= 1. {
+                continue;
+            }
+            let amount = 1. - lit;
+            let mut pixel = GenericImageView::get_pixel(canvas, x, y);
+            let gray = 0.299 * pixel.0[0] as f32 + 0.587 * pixel.0[1] as f32 + 0.114 * pixel.0[2] as f32;
+            for c in 0..3 {
+                let value = pixel.0[c] as f32;
+                let changed = (value + (gray - value) * desaturate) * (1. - dim);
+                pixel.0[c] = (value + (changed - value) * amount).round() as u8;
+            }
+            canvas.draw_pixel(x, y, pixel);
+        }
+    }
+}
+
+/// Placement of a magnifier: the region [min]-[max] is shown enlarged by [factor] in an inset centered in [inset_center].
+/// The inset is a circle enclosing the region if [circle] is true, otherwise a rectangle
+#[derive(Clone, Copy)]
+pub struct MagnifierGeometry {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+    pub inset_center: (f32, f32),
+    pub factor: f32,
+    pub circle: bool,
+}
+
+/// Draw a magnifier: the region of [source] given by [geometry] is outlined and shown enlarged in its inset, joined to the region by a connector line
+pub fn draw_magnifier_mut(canvas: &mut DynamicImage, source: &DynamicImage, geometry: MagnifierGeometry, thickness: usize, color: [u8; 4]) {
+    let MagnifierGeometry { min, max, inset_center, factor, circle } = geometry;
+    let (width, height) = GenericImageView::dimensions(canvas);
+    let (source_width, source_height) = GenericImageView::dimensions(source);
+    let t = thickness.max(1) as f32;
+    let center = ((min.0 + max.0) / 2., (min.1 + max.1) / 2.);
+    let half = if circle {
+        let r = (max.0 - min.0).max(max.1 - min.1) / 2.;
+        (r, r)
+    } else {
+        ((max.0 - min.0) / 2., (max.1 - min.1) / 2.)
+    };
+    let inset_half = (half.0 * factor, half.1 * factor);
+    let distance = |p: (f32, f32), c: (f32, f32), h: (f32, f32)| if circle {
+        ((p.0 - c.0).powi(2) + (p.1 - c.1).powi(2)).sqrt() - h.0
+    } else {
+        rounded_rect_distance(p, (c.0 - h.0, c.1 - h.1), (c.0 + h.0, c.1 + h.1), 0.)
+    };
+
+    //Connector between the borders of the two shapes, only if they don't overlap
+    let direction = (inset_center.0 - center.0, inset_center.1 - center.1);
+    let start = shape_exit_point(center, half, circle, direction);
+    let end = shape_exit_point(inset_center, inset_half, circle, (-direction.0, -direction.1));
+    if (end.0 - start.0) * direction.0 + (end.1 - start.1) * direction.1 > 0. {
+        draw_thick_line(canvas, start, end, thickness.max(1), color);
+    }
+
+    //Outline of the magnified region, drawn outside it so that it doesn't hide any detail
+    let bounds = |c: (f32, f32), h: (f32, f32), margin: f32| (
+        ((c.0 - h.0 - margin).floor().max(0.) as u32).min(width), ((c.1 - h.1 - margin).floor().max(0.) as u32).min(height),
+        ((c.0 + h.0 + margin).ceil().max(0.) as u32).min(width), ((c.1 + h.1 + margin).ceil().max(0.) as u32).min(height),
+    );
+    let (left, top, right, bottom) = bounds(center, half, t + 1.);
+    for y in top..bottom {
+        for x in left..right {
+            let d = distance((x as f32 + 0.5, y as f32 + 0.5), center, half);
+            let coverage = (0.5 - (d - t / 2.).abs() + t / 2.).clamp(0., 1.);
+            blend_pixel_mut(canvas, x, y, color, coverage);
+        }
+    }
+
+    //Inset: every pixel takes the source pixel it comes from, so that details stay sharp, then the border is drawn around it
+    let (left, top, right, bottom) = bounds(inset_center, inset_half, 1.);
+    for y in top..bottom {
+        for x in left..right {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+            let d = distance(p, inset_center, inset_half);
+            let outer = (0.5 - d).clamp(0., 1.);
+            if outer <= 0. {
+                continue;
+            }
+            let sx = center.0 + (p.0 - inset_center.0) / factor;
+            let sy = center.1 + (p.1 - inset_center.1) / factor;
+            if sx >= 0. && sy >= 0. && (sx as u32) < source_width && (sy as u32) < source_height {
+                blend_pixel_mut(canvas, x, y, GenericImageView::get_pixel(source, sx as u32, sy as u32).0, outer);
+            }
+            let inner = (0.5 - d - t).clamp(0., 1.);
+            blend_pixel_mut(canvas, x, y, color, outer - inner);
+        }
+    }
+}
+
+/// Average color of the square of side [size] centered in (x, y), clipped to the image
+pub fn sample_color(img: &DynamicImage, x: u32, y: u32, size: u32) -> [u8; 4] {
+    let (width, height) = GenericImageView::dimensions(img);
+    let half = size / 2;
+    let mut sum = [0u32; 4];
+    let mut count = 0;
+    for py in y.saturating_sub(half)..(y + half + 1).min(height) {
+        for px in x.saturating_sub(half)..(x + half + 1).min(width) {
+            let pixel = GenericImageView::get_pixel(img, px, py).0;
+            for c in 0..4 {
+                sum[c] += pixel[c] as u32;
+            }
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return [0, 0, 0, 0];
+    }
+    [0, 1, 2, 3].map(|c| ((sum[c] + count / 2) / count) as u8)
+}
+
 /// Transform the absolute position ([Pos2]) of the mouse on the application window into a relative position with respect to the given [Rect]
 ///
 /// [Rect] must be meaningful with respect to the application window. (It needs to actually be a part of the application window to obtain a meaningful relative position)
-pub fn into_relative_pos(pos: Pos2, rect: Rect) -> Pos2 {
-    Pos2::new(pos.x - rect.left(), pos.y - rect.top())
-}
\ No newline at end of file
+/// [zoom] is the scale at which the image is shown inside [Rect], so that the result is expressed in image pixels
+pub fn into_relative_pos(pos: Pos2, rect: Rect, zoom: f32) -> Pos2 {
+    Pos2::new((pos.x - rect.left()) / zoom, (pos.y - rect.top()) / zoom)
+}
+
+/// Inverse of [into_relative_pos]: transform a position in image pixels into the absolute position on the application window
+pub fn into_absolute_pos(pos: Pos2, rect: Rect, zoom: f32) -> Pos2 {
+    Pos2::new(rect.left() + pos.x * zoom, rect.top() + pos.y * zoom)
+}
+/// Find the area of [img] that remains after removing the borders made of a uniform color or of fully transparent pixels.
+/// Each side is compared with its nearest corner, a pixel belongs to the border if every channel differs by at most [tolerance].
+/// Returns None if there is nothing to remove, or if the whole image is uniform
+pub fn find_trim_area(img: &DynamicImage, tolerance: u8) -> Option<(u32, u32, u32, u32)> {
+    let img = img.to_rgba8();
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let is_border = |x: u32, y: u32, reference: image::Rgba<u8>| {
+        let pixel = img.get_pixel(x, y);
+        pixel[3] == 0 || pixel.0.iter().zip(reference.0.iter()).all(|(a, b)| a.abs_diff(*b) <= tolerance)
+    };
+    let top_left = *img.get_pixel(0, 0);
+    let bottom_right = *img.get_pixel(width - 1, height - 1);
+
+    let top = (0..height).find(|&y| !(0..width).all(|x| is_border(x, y, top_left)))?;
+    let bottom = (top..height).rev().find(|&y| !(0..width).all(|x| is_border(x, y, bottom_right)))?;
+    let left = (0..width).find(|&x| !(top..=bottom).all(|y| is_border(x, y, top_left)))?;
+    let right = (left..width).rev().find(|&x| !(top..=bottom).all(|y| is_border(x, y, bottom_right)))?;
+
+    if top == 0 && left == 0 && bottom == height - 1 && right == width - 1 {
+        return None;
+    }
+    Some((left, top, right - left + 1, bottom - top + 1))
+}
+
+/// Minimum length, in pixels, of a straight edge to be considered a side of a window
+const MIN_FRAME_SIDE: u32 = 60;
+/// Maximum number of candidate edges considered on each side of the click point
+const MAX_FRAME_CANDIDATES: usize = 6;
+
+/// True if there is an edge pixel at (x, y), tolerating a 1 pixel misalignment across the direction of the line
+fn is_edge_near(edges: &image::GrayImage, x: i64, y: i64, vertical: bool) -> bool {
+    let (width, height) = edges.dimensions();
+    (-1..=1).any(|d| {
+        let (x, y) = if vertical { (x + d, y) } else { (x, y + d) };
+        x >= 0 && y >= 0 && x < width as i64 && y < height as i64 && edges.get_pixel(x as u32, y as u32)[0] > 0
+    })
+}
+
+/// Length of the straight run of edge pixels passing through (x, y), vertical or horizontal
+fn edge_run(edges: &image::GrayImage, x: u32, y: u32, vertical: bool) -> u32 {
+    let step = |i: i64| if vertical { (x as i64, y as i64 + i) } else { (x as i64 + i, y as i64) };
+    let mut length = 0;
+    for dir in [1i64, -1] {
+        let mut i = if dir > 0 { 0 } else { -1 };
+        loop {
+            let (px, py) = step(i);
+            if !is_edge_near(edges, px, py, vertical) {
+                break;
+            }
+            length += 1;
+            i += dir;
+        }
+    }
+    length
+}
+
+/// Fraction of the segment from (x0, y0) of the given length that lies on an edge
+fn edge_coverage(edges: &image::GrayImage, x0: u32, y0: u32, length: u32, vertical: bool) -> f32 {
+    let covered = (0..length)
+        .filter(|&i| {
+            let (x, y) = if vertical { (x0, y0 + i) } else { (x0 + i, y0) };
+            is_edge_near(edges, x as i64, y as i64, vertical)
+        })
+        .count();
+    covered as f32 / length.max(1) as f32
+}
+
+/// Minimum length of a straight edge to be used as a guide
+const MIN_GUIDE_RUN: u32 = 40;
+
+/// Longest run of edge pixels along the column (or row, if not [vertical]) [i]
+fn longest_edge_run(edges: &image::GrayImage, i: u32, vertical: bool) -> u32 {
+    let (width, height) = edges.dimensions();
+    let length = if vertical { height } else { width };
+    let (mut best, mut run) = (0, 0);
+    for j in 0..length {
+        let (x, y) = if vertical { (i, j) } else { (j, i) };
+        if is_edge_near(edges, x as i64, y as i64, vertical) {
+            run += 1;
+            best = best.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    best
+}
+
+/// Detect the long vertical and horizontal edges of the image (window borders, panels, buttons...), to snap annotations to them.
+/// Returns the x of the vertical ones and the y of the horizontal ones
+pub fn find_ui_edges(img: &DynamicImage) -> (Vec<u32>, Vec<u32>) {
+    let edges = imageproc::edges::canny(&img.to_luma8(), 20., 50.);
+    let (width, height) = edges.dimensions();
+    let lines = |count: u32, vertical: bool| {
+        let mut found: Vec<u32> = Vec::new();
+        for i in 0..count {
+            if longest_edge_run(&edges, i, vertical) >= MIN_GUIDE_RUN {
+                //The same edge is found on adjacent lines because of the tolerance, keep only the first
+                match found.last() {
+                    Some(&last) if i - last <= 2 => {}
+                    _ => found.push(i),
+                }
+            }
+        }
+        found
+    };
+    (lines(width, true), lines(height, false))
+}
+
+/// Detect the dominant rectangular frame (e.g. a window) surrounding [point], using edge detection.
+/// Returns the (x, y, width, height) of the area inside the image, or None if no frame is found
+pub fn find_window_frame(img: &DynamicImage, point: (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    let edges = imageproc::edges::canny(&img.to_luma8(), 20., 50.);
+    let (width, height) = edges.dimensions();
+    let (px, py) = point;
+    if px >= width || py >= height {
+        return None;
+    }
+    // Long straight edges crossing the row/column of the click point, nearest first
+    let candidates = |range: Box<dyn Iterator<Item = u32>>, vertical: bool| -> Vec<u32> {
+        range
+            .filter(|&i| {
+                let (x, y) = if vertical { (i, py) } else { (px, i) };
+                edges.get_pixel(x, y)[0] > 0 && edge_run(&edges, x, y, vertical) >= MIN_FRAME_SIDE
+            })
+            .take(MAX_FRAME_CANDIDATES)
+            .collect()
+    };
+    let lefts = candidates(Box::new((0..px).rev()), true);
+    let rights = candidates(Box::new(px + 1..width), true);
+    let tops = candidates(Box::new((0..py).rev()), false);
+    let bottoms = candidates(Box::new(py + 1..height), false);
+
+    // Among the rectangles whose four sides are (almost) entirely on edges, keep the largest one
+    let mut best: Option<(u32, u32, u32, u32)> = None;
+    for &left in &lefts {
+        for &right in &rights {
+            for &top in &tops {
+                for &bottom in &bottoms {
+                    let (w, h) = (right - left + 1, bottom - top + 1);
+                    if w < MIN_FRAME_SIDE || h < MIN_FRAME_SIDE {
+                        continue;
+                    }
+                    if best.is_some_and(|b| b.2 * b.3 >= w * h) {
+                        continue;
+                    }
+                    let valid = edge_coverage(&edges, left, top, h, true) >= 0.8
+                        && edge_coverage(&edges, right, top, h, true) >= 0.8
+                        && edge_coverage(&edges, left, top, w, false) >= 0.8
+                        && edge_coverage(&edges, left, bottom, w, false) >= 0.8;
+                    if valid {
+                        best = Some((left, top, w, h));
+                    }
+                }
+            }
+        }
+    }
+    best
+}
@@ -2,34 +2,151 @@ mod shortcuts;
 mod image_proc_extra_mod;
 mod editing_mod;
 mod config_mod;
+mod export_mod;
+mod text_mod;
 
 use crate::screen::{self, take_screenshot};
 use crate::gui::image_proc_extra_mod::*;
 use crate::gui::editing_mod::*;
 use crate::gui::config_mod::*;
+use crate::gui::export_mod::*;
+use crate::gui::text_mod::TextAction;
 
-use eframe::egui::{Align, Button, CentralPanel, ColorImage, ComboBox, Context, CursorIcon, ImageButton, Label, Layout, Pos2, Response, ScrollArea, Sense, Slider, TopBottomPanel, Ui, Window};
+use eframe::egui::{Align, Align2, Button, CentralPanel, Color32, ComboBox, Context, RichText, TextEdit, CursorIcon, DragValue, FontId, ImageButton, Label, Layout, PointerButton, Pos2, Response, ScrollArea, Sense, Slider, TextureOptions, TopBottomPanel, Ui, Window};
 use arboard::Clipboard;
 use eframe::{run_native, NativeOptions};
 use eframe::{App, Frame};
 use egui_extras::RetainedImage;
-use image::DynamicImage;
+use image::{DynamicImage, RgbaImage};
+use image::imageops::FilterType;
 use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager};
 use rfd::FileDialog;
 use screenshots::DisplayInfo;
 use std::borrow::Cow;
-use std::cmp::max;
-use std::collections::HashMap;
-use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 use std::time::{Duration, UNIX_EPOCH};
 use eframe::emath::Rect;
-use egui::{Event, InputState, Vec2};
-use rusttype::{Font, Scale};
+use egui::{Event, InputState, Key, Stroke, Vec2};
 
 use self::shortcuts::{ShortcutManager, SaveHotKeys};
 
+/// Side, in screen points, of the squares used to resize the crop area
+const CROP_HANDLE_SIZE: f32 = 8.;
+/// Side, in pixels, of the stickers and emoji when inserted
+const STICKER_SIZE: u32 = 128;
+
+/// Small button filled with [color]
+fn color_swatch(ui: &mut Ui, color: [u8; 4]) -> Response {
+    let fill = Color32::from_rgba_unmultiplied(color[0], color[1], color[2], color[3]);
+    ui.add(Button::new("").fill(fill).min_size(Vec2::splat(18.))).on_hover_text(color_to_hex(color))
+}
+
+/// Draw the shape being dragged over the image drawn in [image_rect], as it will look once released
+fn draw_shape_preview(painter: &egui::Painter, paint_info: &PaintState, image_rect: Rect, zoom: f32) {
+    let to_screen = |p: Pos2| into_absolute_pos(p, image_rect, zoom);
+    let [r, g, b, a] = paint_info.curr_color;
+    let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+    //Lines of thickness t are drawn with circles of radius t
+    let width = (2 * paint_info.curr_thickness + 1) as f32 * zoom;
+    let stroke = Stroke::new(width, color);
+    let (first, second) = paint_info.shape_points();
+    match paint_info.curr_tool {
+        Tool::HollowRect => painter.rect_stroke(Rect::from_two_pos(to_screen(first), to_screen(second)), 0., stroke),
+        Tool::FilledRect => painter.rect_filled(Rect::from_two_pos(to_screen(first), to_screen(second)), 0., color),
+        Tool::HollowCircle => painter.circle_stroke(to_screen(first), (second - first).length() * zoom, stroke),
+        Tool::FilledCircle => painter.circle_filled(to_screen(first), (second - first).length() * zoom, color),
+        Tool::HollowEllipse | Tool::FilledEllipse => {
+            let bounds = Rect::from_two_pos(to_screen(first), to_screen(second));
+            let points: Vec<Pos2> = (0..64).map(|i| {
+                let angle = i as f32 / 64. * std::f32::consts::TAU;
+                bounds.center() + Vec2::new(angle.cos() * bounds.width() / 2., angle.sin() * bounds.height() / 2.)
+            }).collect();
+            if paint_info.curr_tool == Tool::FilledEllipse {
+                painter.add(egui::Shape::convex_polygon(points, color, Stroke::NONE));
+            } else {
+                painter.add(egui::Shape::closed_line(points, stroke));
+            }
+        }
+        Tool::Arrow => {
+            let ArrowGeometry { points, heads, size } = paint_info.arrow_geometry();
+            let points: Vec<Pos2> = points.iter().map(|p| to_screen(Pos2::new(p.0, p.1))).collect();
+            match paint_info.arrow_style.pattern.dash(paint_info.curr_thickness) {
+                //Dots are as long as the line is wide
+                Some((on, off)) => {
+                    let on = on.max(width / zoom);
+                    painter.extend(egui::Shape::dashed_line(&points, stroke, on * zoom, off * zoom));
+                }
+                None => {
+                    painter.add(egui::Shape::line(points, stroke));
+                }
+            }
+            for (tip, direction) in heads {
+                let v = direction.normalized();
+                let side = v.rot90() * size;
+                let (p1, p2) = (to_screen(tip - v * size - side), to_screen(tip - v * size + side));
+                if paint_info.arrow_style.head == ArrowHead::Filled {
+                    painter.add(egui::Shape::convex_polygon(vec![to_screen(tip), p1, p2], color, Stroke::NONE));
+                } else {
+                    painter.line_segment([p1, to_screen(tip)], stroke);
+                    painter.line_segment([p2, to_screen(tip)], stroke);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Draw rulers along the top and left side of [visible], the part shown of the image drawn in [image_rect],
+/// with the position of the pointer marked on them
+fn draw_rulers(painter: &egui::Painter, visible: Rect, image_rect: Rect, zoom: f32, pointer: Option<Pos2>) {
+    const THICKNESS: f32 = 18.;
+    //Distance between ticks, in image pixels, so that they are not too close on the screen
+    let steps = [1., 2., 5., 10., 20., 50., 100., 200., 500., 1000., 2000.];
+    let minor = steps.iter().copied().find(|step| step * zoom >= 6.).unwrap_or(2000.);
+    let major = steps.iter().copied().find(|step| step * zoom >= 60. && (step / minor).fract() == 0.).unwrap_or(minor * 10.);
+    let top = Rect::from_min_size(visible.min, Vec2::new(visible.width(), THICKNESS));
+    let left = Rect::from_min_size(visible.min + Vec2::new(0., THICKNESS), Vec2::new(THICKNESS, visible.height() - THICKNESS));
+    painter.rect_filled(top, 0., Color32::from_black_alpha(170));
+    painter.rect_filled(left, 0., Color32::from_black_alpha(170));
+    let stroke = Stroke::new(1., Color32::from_white_alpha(200));
+    let font = FontId::monospace(9.);
+    let first = |from: f32| ((from / zoom) / minor).floor() * minor;
+    let mut x = first(visible.left() - image_rect.left());
+    while image_rect.left() + x * zoom <= visible.right() {
+        let screen_x = image_rect.left() + x * zoom;
+        if screen_x >= visible.left() {
+            let is_major = (x / major).fract() == 0.;
+            let length = if is_major { THICKNESS } else { THICKNESS / 3. };
+            painter.vline(screen_x, top.bottom() - length..=top.bottom(), stroke);
+            if is_major {
+                painter.text(Pos2::new(screen_x + 2., top.top() + 1.), Align2::LEFT_TOP, format!("{}", x), font.clone(), Color32::WHITE);
+            }
+        }
+        x += minor;
+    }
+    let mut y = first(visible.top() + THICKNESS - image_rect.top());
+    while image_rect.top() + y * zoom <= visible.bottom() {
+        let screen_y = image_rect.top() + y * zoom;
+        if screen_y >= left.top() {
+            let is_major = (y / major).fract() == 0.;
+            let length = if is_major { THICKNESS } else { THICKNESS / 3. };
+            painter.hline(left.right() - length..=left.right(), screen_y, stroke);
+            if is_major {
+                painter.text(Pos2::new(left.left() + 1., screen_y + 2.), Align2::LEFT_TOP, format!("{}", y), font.clone(), Color32::WHITE);
+            }
+        }
+        y += minor;
+    }
+    if let Some(pos) = pointer {
+        let marker = Stroke::new(1., Color32::from_rgb(255, 230, 0));
+        painter.vline(pos.x, top.y_range(), marker);
+        painter.hline(left.x_range(), pos.y, marker);
+    }
+}
+
 fn select_display(index: usize) -> Option<DisplayInfo> {
     let mydisp = DisplayInfo::all();
     match mydisp {
@@ -40,6 +157,20 @@ fn select_display(index: usize) -> Option<DisplayInfo> {
     }
 }
 
+/// Look for the fonts in [user_dir] and in the system on a worker thread, which sends them once found
+fn scan_fonts(ctx: &Context, user_dir: Option<PathBuf>) -> Receiver<BTreeMap<String, FontFaces>> {
+    let (tx, rx) = channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        match tx.send(load_fonts(user_dir.as_deref())) {
+            Ok(_) => ctx.request_repaint(),
+            //The scan was replaced by a newer one
+            Err(_) => {}
+        }
+    });
+    rx
+}
+
 #[derive(PartialEq, Eq)]
 enum Action {
     Paint,
@@ -52,6 +183,8 @@ struct RustShot {
     receiver: Receiver<DynamicImage>,
     sender: Sender<DynamicImage>,
     paint_info: PaintState,
+    view: ViewState,
+    canvas: CanvasTexture,
     action: Action,
     timer: Option<u64>,
     show_confirmation_dialog: bool,
@@ -59,8 +192,37 @@ struct RustShot {
     shortcuts: ShortcutManager,
     icons: HashMap<String, Result<RetainedImage, String>>,
     tooltips: HashMap<String, String>,
-    fonts: HashMap<String, Option<Font<'static>>>,
+    fonts: BTreeMap<String, FontFaces>,
+    //Fonts found on disk by the worker thread scanning for them
+    fonts_receiver: Receiver<BTreeMap<String, FontFaces>>,
+    //Font used in the last session, selected once the fonts on disk are found
+    pending_font: Option<String>,
+    //User fonts folder the fonts were scanned from, to scan again when it changes in the settings
+    fonts_path: Option<PathBuf>,
+    font_filter: String,
     shape_window_open: bool,
+    color_window_open: bool,
+    //The last trim found no border, told to the user until the pointer leaves the trim button
+    nothing_to_trim: bool,
+    layers_window_open: bool,
+    //The tmp image has a change to the layers not stacked yet, while the opacity slider is dragged
+    layer_previewed: bool,
+    //The picture or the clipboard image last added as a layer could not be loaded
+    layers_error: Option<String>,
+    adjust_window_open: bool,
+    //Adjustments of the capture shown in the adjustments window, previewed until they are applied
+    adjustments: Adjustments,
+    transform_window_open: bool,
+    resize_info: ResizeManager,
+    frame_window_open: bool,
+    frame_info: FrameSettings,
+    watermark_window_open: bool,
+    //The watermark settings changed and are saved once the user stops editing them
+    watermark_dirty: bool,
+    //The logo of the active watermark could not be loaded when the image was last exported
+    watermark_error: Option<String>,
+    //Cached preview of the final image as it is exported, None when it needs to be computed again
+    export_preview: Option<DynamicImage>,
     rx_global: Receiver<GlobalHotKeyEvent>,
 }
 
@@ -73,7 +235,8 @@ impl RustShot {
         // for e.g. egui::PaintCallback.
         let (tx, rx) = channel();
         let (icons_map, tooltips_map) = load_icons();
-        let fonts_map = load_fonts();
+        let shortcuts = ShortcutManager::new();
+        let fonts_receiver = scan_fonts(&cc.egui_ctx, shortcuts.fonts_path.clone());
         let (tx_global,rx_global) = channel();
         let c = cc.egui_ctx.clone();
         
@@ -88,22 +251,63 @@ impl RustShot {
             }
         });
         
-        RustShot {
+        let mut app = RustShot {
             curr_screenshot: None,
             display: Some(0),
             receiver: rx,
             sender: tx,
             paint_info: PaintState::new(),
+            view: ViewState::new(),
+            canvas: CanvasTexture::new(),
             action: Action::None,
             timer: Some(0),
             allowed_to_close: true,
             show_confirmation_dialog: false,
-            shortcuts: ShortcutManager::new(),
+            fonts_path: shortcuts.fonts_path.clone(),
+            shortcuts,
             icons: icons_map,
             tooltips: tooltips_map,
-            fonts: fonts_map,
+            fonts: embedded_fonts(),
+            fonts_receiver,
+            pending_font: None,
+            font_filter: String::new(),
             shape_window_open: false,
+            color_window_open: false,
+            nothing_to_trim: false,
+            layers_window_open: false,
+            layer_previewed: false,
+            layers_error: None,
+            adjust_window_open: false,
+            adjustments: Adjustments::new(),
+            transform_window_open: false,
+            resize_info: ResizeManager::new(),
+            frame_window_open: false,
+            frame_info: FrameSettings::new(),
+            watermark_window_open: false,
+            watermark_dirty: false,
+            watermark_error: None,
+            export_preview: None,
             rx_global
+        };
+        //Pick again the font used in the last session, if it is still installed
+        if let Some(name) = app.shortcuts.last_font.clone() {
+            if !app.select_font(&name) {
+                app.pending_font = Some(name);
+            }
+        }
+        app
+    }
+
+    /// Use the font family with the given name for the text tool. Return false if it can't be loaded
+    fn select_font(&mut self, name: &str) -> bool {
+        match self.fonts.get(name).map(|faces| faces.load()) {
+            //If the font was not correctly loaded, keep the old one to avoid panic
+            Some(family) if family.regular.is_some() => {
+                self.paint_info.text_info.curr_family = family;
+                self.paint_info.text_info.curr_font_name = name.to_string();
+                true
+            }
+            _ => false,
         }
     }
 
@@ -118,22 +322,16 @@ impl RustShot {
                         let screenshot_save_btn = ui.add(Button::new("💾 Save as")).on_hover_text("Save screenshot as");
                         if screenshot_save_btn.clicked() || self.shortcuts.use_shortcut(ctx, &KeyCommand::SaveScreenshot)
                         {
-                            match &self.curr_screenshot {
-                                Some(screenshot) => {
-                                    self.save_screenshot(&screenshot.get_final_image().get_image());
-                                }
-                                None => {}
+                            if let Some(screenshot) = self.get_export_image() {
+                                self.save_screenshot(&screenshot);
                             }
                         }
 
                         let screenshot_save_default_btn = ui.add(Button::new("💾 Save")).on_hover_text("Save screenshot in default path");
                         if screenshot_save_default_btn.clicked() || self.shortcuts.use_shortcut(ctx, &KeyCommand::QuickSaveScreenshot)
                         {
-                            match &self.curr_screenshot {
-                                Some(screenshot) => {
-                                    self.save_default_screenshot(&screenshot.get_final_image().get_image());
-                                }
-                                None => {}
+                            if let Some(screenshot) = self.get_export_image() {
+                                self.save_default_screenshot(&screenshot);
                             }
                         }
                         let paint_btn = ui.add(Button::new("Edit")).on_hover_text("Edit screenshot");
@@ -141,6 +339,14 @@ impl RustShot {
                         {
                             self.action = Action::Paint;
                         }
+                        let frame_btn = ui.add(Button::new("🖼 Frame")).on_hover_text("Add margin, border and shadow when exporting");
+                        if frame_btn.clicked() {
+                            self.frame_window_open = !self.frame_window_open;
+                        }
+                        let watermark_btn = ui.add(Button::new("💧 Watermark")).on_hover_text("Add a text or a logo when exporting");
+                        if watermark_btn.clicked() {
+                            self.watermark_window_open = !self.watermark_window_open;
+                        }
                     }
                     ComboBox::from_label("")
                         .width(80.0)
@@ -152,6 +358,9 @@ impl RustShot {
                             ui.selectable_value(&mut self.timer, Some(10), "🕓 10 sec").on_hover_text("Delay screenshot");
                         });
                     self.display_selector(ui);
+                    if self.curr_screenshot.is_some() {
+                        self.render_zoom_controls(ui);
+                    }
                     if screenshot_btn.clicked() || self.shortcuts.use_shortcut(ctx, &KeyCommand::TakeScreenshot)
                     {
                         self.store_screenshot(frame, ctx);
@@ -176,30 +385,65 @@ impl RustShot {
     }
 
     fn render_central_panel(&mut self, ctx: &Context, _frame: &mut Frame) {
+        //Show the image as it will be exported, computed and uploaded again only when the image or the settings change
+        let preview_changed = self.action == Action::None && self.export_preview.is_none();
+        if preview_changed {
+            self.export_preview = self.get_export_image();
+        }
         CentralPanel::default().show(ctx, |ui| match &mut self.curr_screenshot {
             //If screenshot is already available, then show it on the GUI
             Some(screenshot) => {
-                let screenshot = match self.action {
-                    Action::None => screenshot.get_final_image().get_image(),
-                    Action::Paint => screenshot.get_tmp_image().get_image(),
+                let (width, height) = match self.action {
+                    Action::None if self.export_preview.is_some() => {
+                        let preview = self.export_preview.as_ref().unwrap();
+                        (preview.width(), preview.height())
+                    }
+                    Action::None => (screenshot.get_final_image_as_ref().get_width(), screenshot.get_final_image_as_ref().get_height()),
+                    Action::Paint => (screenshot.tmp_image.get_width(), screenshot.tmp_image.get_height()),
                 };
-                ScrollArea::both().show_viewport(ui, |ui, rect| {
-                    let retained_img = RetainedImage::from_color_image(
-                        "screenshot",
-                        ColorImage::from_rgba_unmultiplied(
-                            [screenshot.width() as usize, screenshot.height() as usize],
-                            screenshot.as_bytes(),
-                        ),
-                    );
+                if self.view.fit_to_window {
+                    self.view.fit(ui.available_size(), width, height);
+                }
+                // Ctrl + mouse wheel zooms keeping still the point under the pointer
+                let mut scroll_area = ScrollArea::both();
+                let zoom_delta = ui.input(|i| i.zoom_delta());
+                if zoom_delta != 1.0 {
+                    match ui.input(|i| i.pointer.hover_pos()) {
+                        Some(ptr) if self.view.viewport_contains(ptr) => {
+                            scroll_area = scroll_area.scroll_offset(self.view.zoom_around(zoom_delta, ptr));
+                        }
+                        _ => {}
+                    }
+                }
+                let zoom = self.view.zoom;
+                let output = scroll_area.show_viewport(ui, |ui, rect| {
+                    // When zooming in, show the real pixels instead of blurring them
+                    let texture_options = if zoom > 1.0 { TextureOptions::NEAREST } else { TextureOptions::LINEAR };
+                    //While painting only the part of the image changed by the tools is uploaded again
+                    let screenshot = self.curr_screenshot.as_mut().unwrap();
+                    let texture = match self.action {
+                        Action::None if self.export_preview.is_some() => self.canvas.show_preview(ctx, self.export_preview.as_ref().unwrap(), preview_changed, texture_options),
+                        Action::None => self.canvas.show(ctx, &screenshot.get_final_image_as_ref().get_composite(), texture_options),
+                        Action::Paint => {
+                            let changed = screenshot.take_tmp_changed();
+                            self.canvas.show_editing(ctx, &screenshot.tmp_image, changed, texture_options)
+                        }
+                    };
                     let img = ui.add(
-                        ImageButton::new(retained_img.texture_id(ctx), retained_img.size_vec2())
+                        ImageButton::new(texture.id(), texture.size_vec2() * zoom)
                             .frame(false)
                             .sense(Sense::click_and_drag()),
                     );
+                    // Middle-drag pans the canvas, whatever tool is selected
+                    if img.dragged_by(PointerButton::Middle) {
+                        ui.scroll_with_delta(img.drag_delta());
+                        ui.ctx().set_cursor_icon(CursorIcon::Grabbing);
+                    }
                     if self.action == Action::Paint {
                         self.paint_logic(img, ui, rect);
                     }
                 });
+                self.view.update_viewport(output.state.offset, output.inner_rect);
             }
             None => {
                 ScrollArea::both().show(ui, |ui| ui.label("No screenshots yet"));
@@ -216,6 +460,8 @@ impl RustShot {
                         let filled_rect_btn = self.icon_button("square-fill", true, ctx, ui);
                         let hollow_circle_btn = self.icon_button("circle", true, ctx, ui);
                         let filled_circle_btn = self.icon_button("circle-fill", true, ctx, ui);
+                        let hollow_ellipse_btn = self.icon_button("ellipse", true, ctx, ui);
+                        let filled_ellipse_btn = self.icon_button("ellipse-fill", true, ctx, ui);
                         let arrow_btn = self.icon_button("arrow-up-right", true, ctx, ui);
                         if hollow_rect_btn.clicked() {
                             self.paint_info.curr_tool = Tool::HollowRect;
@@ -229,6 +475,12 @@ impl RustShot {
                         if filled_circle_btn.clicked() {
                             self.paint_info.curr_tool = Tool::FilledCircle;
                         }
+                        if hollow_ellipse_btn.clicked() {
+                            self.paint_info.curr_tool = Tool::HollowEllipse;
+                        }
+                        if filled_ellipse_btn.clicked() {
+                            self.paint_info.curr_tool = Tool::FilledEllipse;
+                        }
                         if arrow_btn.clicked() {
                             self.paint_info.curr_tool = Tool::Arrow;
                         }
@@ -241,6 +493,748 @@ impl RustShot {
             });
     }
 
+    /// Renders the style of the text being written: bold/italic, alignment, background box and outline
+    fn render_text_style_window(&mut self, ctx: &Context) {
+        Window::new("Text style").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                let style = &mut self.paint_info.text_info.style;
+                ui.horizontal(|ui| {
+                    ui.toggle_value(&mut style.bold, RichText::new("B").strong());
+                    ui.toggle_value(&mut style.italic, RichText::new("I").italics());
+                    ui.separator();
+                    ui.selectable_value(&mut style.align, TextAlign::Left, "Left");
+                    ui.selectable_value(&mut style.align, TextAlign::Center, "Center");
+                    ui.selectable_value(&mut style.align, TextAlign::Right, "Right");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut style.background, "Background");
+                    ui.add_enabled_ui(style.background, |ui| {
+                        ui.color_edit_button_srgba_unmultiplied(&mut style.background_color);
+                        ui.add(DragValue::new(&mut style.padding).clamp_range(0..=50).prefix("padding: "));
+                    });
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut style.outline, "Outline");
+                    ui.add_enabled_ui(style.outline, |ui| {
+                        ui.color_edit_button_srgba_unmultiplied(&mut style.outline_color);
+                        ui.add(DragValue::new(&mut style.outline_width).clamp_range(1..=8).prefix("width: "));
+                    });
+                });
+            });
+    }
+
+    /// Renders the style of the arrows: heads, curvature and stroke pattern
+    fn render_arrow_style_window(&mut self, ctx: &Context) {
+        Window::new("Arrow style").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                let style = &mut self.paint_info.arrow_style;
+                ui.horizontal(|ui| {
+                    for head in [ArrowHead::None, ArrowHead::Open, ArrowHead::Filled] {
+                        ui.selectable_value(&mut style.head, head, head.to_string());
+                    }
+                    ui.add_enabled(style.head != ArrowHead::None, egui::Checkbox::new(&mut style.double, "Double"));
+                });
+                ui.horizontal(|ui| {
+                    for pattern in [StrokePattern::Solid, StrokePattern::Dashed, StrokePattern::Dotted] {
+                        ui.selectable_value(&mut style.pattern, pattern, pattern.to_string());
+                    }
+                });
+                ui.add(Slider::new(&mut style.bend, -1.0..=1.0).text("Curve"));
+                ui.add_enabled(style.head != ArrowHead::None, Slider::new(&mut style.head_size, 0.5..=3.0).text("Head size"));
+            });
+    }
+
+    /// Renders the settings of the magnifier: shape of the inset and magnification
+    fn render_magnifier_window(&mut self, ctx: &Context) {
+        Window::new("Magnifier").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                let magnifier_info = &mut self.paint_info.magnifier_info;
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut magnifier_info.shape, InsetShape::Circle, InsetShape::Circle.to_string());
+                    ui.selectable_value(&mut magnifier_info.shape, InsetShape::Rectangle, InsetShape::Rectangle.to_string());
+                });
+                ui.add(Slider::new(&mut magnifier_info.factor, 1.5..=8.).text("Zoom").suffix("x"));
+                if magnifier_info.source.is_some() {
+                    ui.label("Drag the inset or the region to move them, press Enter to confirm");
+                } else {
+                    ui.label("Drag over the region to magnify");
+                }
+            });
+    }
+
+    /// Renders the fill of the callouts, which contrasts with the current color unless chosen
+    fn render_callout_window(&mut self, ctx: &Context) {
+        Window::new("Callout").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                let callout_info = &mut self.paint_info.callout_info;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut callout_info.contrasting_fill, "Contrasting fill");
+                    ui.add_enabled_ui(!callout_info.contrasting_fill, |ui| {
+                        ui.color_edit_button_srgba_unmultiplied(&mut callout_info.fill_color);
+                    });
+                });
+                if callout_info.rect.is_some() {
+                    ui.label("Type the text, drag the box or the tail to move them, press Escape to complete it");
+                } else {
+                    ui.label("Click where the box of the callout goes");
+                }
+            });
+    }
+
+    /// Recent colors, saved palettes and the current color in the common formats
+    fn render_color_window(&mut self, ctx: &Context) {
+        let mut open = true;
+        Window::new("Colors").collapsible(true).resizable(false).open(&mut open)
+            .show(ctx, |ui| {
+                let paint_info = &mut self.paint_info;
+                ui.label("Recent");
+                ui.horizontal_wrapped(|ui| {
+                    for color in paint_info.colors.recent.clone() {
+                        if color_swatch(ui, color).clicked() {
+                            paint_info.curr_color = color;
+                        }
+                    }
+                });
+                ui.separator();
+                let palettes = &mut self.shortcuts.palettes;
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    let selected = paint_info.colors.selected_palette.min(palettes.len().saturating_sub(1));
+                    ComboBox::from_label("Palette")
+                        .selected_text(palettes.get(selected).map(|p| p.name.clone()).unwrap_or("None".to_string()))
+                        .show_ui(ui, |ui| {
+                            for (i, palette) in palettes.iter().enumerate() {
+                                ui.selectable_value(&mut paint_info.colors.selected_palette, i, palette.name.clone());
+                            }
+                        });
+                    if palettes.get(selected).is_some() && ui.button("Delete").clicked() {
+                        palettes.remove(selected);
+                        paint_info.colors.selected_palette = 0;
+                        changed = true;
+                    }
+                });
+                if let Some(palette) = palettes.get_mut(paint_info.colors.selected_palette) {
+                    ui.horizontal_wrapped(|ui| {
+                        let mut removed = None;
+                        for (i, color) in palette.colors.iter().enumerate() {
+                            let swatch = color_swatch(ui, *color).on_hover_text("Click to use, right click to remove");
+                            if swatch.clicked() {
+                                paint_info.curr_color = *color;
+                            }
+                            if swatch.secondary_clicked() {
+                                removed = Some(i);
+                            }
+                        }
+                        if let Some(i) = removed {
+                            palette.colors.remove(i);
+                            changed = true;
+                        }
+                        if ui.button("+").on_hover_text("Add the current color").clicked() && !palette.colors.contains(&paint_info.curr_color) {
+                            palette.colors.push(paint_info.curr_color);
+                            changed = true;
+                        }
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.add(TextEdit::singleline(&mut paint_info.colors.new_palette_name).hint_text("New palette").desired_width(120.));
+                    let name = paint_info.colors.new_palette_name.trim().to_string();
+                    if ui.add_enabled(!name.is_empty(), Button::new("Create")).clicked() {
+                        palettes.push(Palette { name, colors: vec![paint_info.curr_color] });
+                        paint_info.colors.selected_palette = palettes.len() - 1;
+                        paint_info.colors.new_palette_name.clear();
+                        changed = true;
+                    }
+                });
+                if changed {
+                    self.shortcuts.save_palettes();
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Eyedropper sample");
+                    ComboBox::from_id_source("sample_size")
+                        .selected_text(format!("{0}x{0}", paint_info.colors.sample_size))
+                        .show_ui(ui, |ui| {
+                            for size in [1, 3, 5, 9] {
+                                ui.selectable_value(&mut paint_info.colors.sample_size, size, format!("{0}x{0}", size));
+                            }
+                        });
+                });
+                let color = paint_info.curr_color;
+                ui.horizontal(|ui| {
+                    color_swatch(ui, color);
+                    for text in [color_to_hex(color), color_to_rgb(color), color_to_hsl(color)] {
+                        if ui.button(text.clone()).on_hover_text("Copy").clicked() {
+                            ui.output_mut(|o| o.copied_text = text);
+                        }
+                    }
+                });
+            });
+        if !open {
+            self.color_window_open = false;
+            if self.paint_info.curr_tool == Tool::Eyedropper {
+                self.paint_info.curr_tool = self.paint_info.colors.previous_tool;
+            }
+        }
+    }
+
+    /// Layers of the image, from the top one. The tools draw on the selected layer, and every change, selection included, is a step that can be undone
+    fn render_layers_window(&mut self, ctx: &Context) {
+        let curr_screenshot = match &self.curr_screenshot {
+            Some(screenshot) => screenshot,
+            None => return,
+        };
+        let layers = curr_screenshot.tmp_image.get_layers().to_vec();
+        let active = curr_screenshot.tmp_image.get_active_layer();
+        let mut open = true;
+        let mut change = None;
+        //The opacity being dragged is only previewed, and stacked once the slider is released
+        let mut preview = false;
+        let mut released = false;
+        let enabled = !self.paint_info.editing_in_place();
+        Window::new("Layers").open(&mut open).collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                ui.set_enabled(enabled);
+                for (index, layer) in layers.iter().enumerate().rev() {
+                    ui.horizontal(|ui| {
+                        let mut visible = layer.visible;
+                        if ui.checkbox(&mut visible, "").on_hover_text("Show").changed() {
+                            change = Some(LayerChange::Show(index, visible));
+                        }
+                        if ui.selectable_label(index == active, &layer.name).clicked() {
+                            change = Some(LayerChange::Select(index));
+                        }
+                        if ui.add_enabled(index + 1 < layers.len(), Button::new("⏶")).on_hover_text("Move up").clicked() {
+                            change = Some(LayerChange::Move(index, true));
+                        }
+                        if ui.add_enabled(index > 0, Button::new("⏷")).on_hover_text("Move down").clicked() {
+                            change = Some(LayerChange::Move(index, false));
+                        }
+                        if ui.add_enabled(layers.len() > 1, Button::new("🗑")).on_hover_text("Remove").clicked() {
+                            change = Some(LayerChange::Remove(index));
+                        }
+                    });
+                }
+                ui.separator();
+                let layer = &layers[active];
+                let mut opacity = layer.opacity;
+                let opacity_slider = ui.add(Slider::new(&mut opacity, 0.0..=1.).text("Opacity").custom_formatter(|v, _| format!("{:.0}%", v * 100.)));
+                if opacity_slider.changed() {
+                    change = Some(LayerChange::Opacity(opacity));
+                    preview = opacity_slider.dragged();
+                }
+                released = opacity_slider.drag_released();
+                ComboBox::from_label("Blend")
+                    .selected_text(layer.blend.to_string())
+                    .show_ui(ui, |ui| {
+                        for blend in BlendMode::ALL {
+                            if ui.selectable_label(layer.blend == blend, blend.to_string()).clicked() {
+                                change = Some(LayerChange::Blend(blend));
+                            }
+                        }
+                    });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("New layer").on_hover_text("Transparent layer to draw on").clicked() {
+                        change = Some(LayerChange::Add(LayerKind::Annotations, None));
+                    }
+                    if ui.button("Picture...").clicked() {
+                        if let Some(path) = FileDialog::new().add_filter("Images", &["png", "jpg", "jpeg", "gif", "bmp", "webp"]).pick_file() {
+                            match image::open(&path) {
+                                Ok(img) => change = Some(LayerChange::Add(LayerKind::Picture, Some(img))),
+                                Err(err) => self.layers_error = Some(err.to_string()),
+                            }
+                        }
+                    }
+                    if ui.button("Paste").on_hover_text("Add the image in the clipboard as a layer").clicked() {
+                        match Clipboard::new().and_then(|mut clipboard| clipboard.get_image()) {
+                            Ok(data) => {
+                                if let Some(img) = RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned()) {
+                                    change = Some(LayerChange::Add(LayerKind::Picture, Some(DynamicImage::ImageRgba8(img))));
+                                }
+                            }
+                            Err(err) => self.layers_error = Some(err.to_string()),
+                        }
+                    }
+                });
+                if let Some(error) = &self.layers_error {
+                    ui.label(RichText::new(error).color(Color32::RED));
+                }
+            });
+        if !open {
+            self.layers_window_open = false;
+        }
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        match change {
+            Some(change) => {
+                self.layers_error = None;
+                let mut img = curr_screenshot.get_tmp_image();
+                change.apply(&mut img);
+                if preview {
+                    self.layer_previewed = true;
+                } else {
+                    curr_screenshot.stack_image(img.clone());
+                    self.layer_previewed = false;
+                }
+                curr_screenshot.set_tmp_image(img);
+            }
+            None if released && self.layer_previewed => {
+                curr_screenshot.stack_image(curr_screenshot.get_tmp_image());
+                self.layer_previewed = false;
+            }
+            None => {}
+        }
+    }
+
+    /// Adjust the colors of the capture, previewed on the image until they are applied as a step that can be undone
+    fn render_adjustments_window(&mut self, ctx: &Context) {
+        let old_adjustments = self.adjustments;
+        let (mut apply, mut cancel) = (false, false);
+        let enabled = !self.paint_info.editing_in_place();
+        Window::new("Adjustments").collapsible(false).resizable(false)
+            .show(ctx, |ui| {
+                ui.set_enabled(enabled);
+                let adjustments = &mut self.adjustments;
+                let percentage = |v: f64, _| format!("{:+.0}%", v * 100.);
+                ui.add(Slider::new(&mut adjustments.brightness, -1.0..=1.).text("Brightness").custom_formatter(percentage));
+                ui.add(Slider::new(&mut adjustments.contrast, -1.0..=1.).text("Contrast").custom_formatter(percentage));
+                ui.add(Slider::new(&mut adjustments.gamma, 0.2..=5.).logarithmic(true).text("Gamma"));
+                ui.add_enabled(!adjustments.grayscale, Slider::new(&mut adjustments.saturation, 0.0..=2.).text("Saturation").custom_formatter(|v, _| format!("{:.0}%", v * 100.)));
+                ui.add(Slider::new(&mut adjustments.sharpen, 0.0..=2.).text("Sharpen"));
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut adjustments.grayscale, "Grayscale");
+                    ui.checkbox(&mut adjustments.invert, "Invert");
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        *adjustments = Adjustments::new();
+                    }
+                    apply = ui.button("Apply").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            });
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        if apply {
+            let mut img = curr_screenshot.get_last_image();
+            img.set_adjustments(self.adjustments);
+            curr_screenshot.stack_image(img.clone());
+            curr_screenshot.set_tmp_image(img);
+            self.adjust_window_open = false;
+        } else if cancel {
+            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+            self.adjust_window_open = false;
+        } else if self.adjustments != old_adjustments {
+            let mut img = curr_screenshot.get_tmp_image();
+            img.set_adjustments(self.adjustments);
+            curr_screenshot.set_tmp_image(img);
+        }
+    }
+
+    /// Choose what to insert: an image file, a sticker or an emoji
+    fn render_insert_window(&mut self, ctx: &Context) {
+        let mut chosen = None;
+        Window::new("Insert").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                if ui.button("Image file...").clicked() {
+                    if let Some(path) = FileDialog::new().add_filter("Images", &["png", "jpg", "jpeg", "gif", "bmp", "webp"]).pick_file() {
+                        match image::open(&path) {
+                            Ok(img) => chosen = Some(img),
+                            Err(err) => self.paint_info.insert_info.error = Some(err.to_string()),
+                        }
+                    }
+                }
+                ui.horizontal(|ui| {
+                    for (name, _, _) in STICKERS {
+                        if self.icon_button(name, true, ctx, ui).clicked() {
+                            chosen = sticker_image(name, STICKER_SIZE);
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(TextEdit::singleline(&mut self.paint_info.insert_info.emoji).hint_text("Emoji").desired_width(60.));
+                    let emoji = self.paint_info.insert_info.emoji.trim().to_string();
+                    if ui.add_enabled(!emoji.is_empty(), Button::new("Insert")).clicked() {
+                        match self.emoji_image(&emoji) {
+                            Some(img) => chosen = Some(img),
+                            None => self.paint_info.insert_info.error = Some("No font has this character".to_string()),
+                        }
+                    }
+                });
+                if let Some(error) = &self.paint_info.insert_info.error {
+                    ui.label(RichText::new(error).color(Color32::RED));
+                }
+                if self.paint_info.insert_info.rect.is_some() {
+                    ui.label("Drag to move it, drag the corner to resize it.\nPress Enter to confirm, Escape to remove it");
+                }
+            });
+        if let Some(content) = chosen {
+            self.paint_info.insert_info.error = None;
+            self.start_insert(content);
+        }
+    }
+
+    /// Render [text] with the current color, using the first font having all its characters, emoji and symbol fonts first
+    fn emoji_image(&self, text: &str) -> Option<DynamicImage> {
+        //Variation selectors and joiners have no glyph of their own
+        let text: String = text.chars().filter(|c| !matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{200D}')).collect();
+        let mut names: Vec<&String> = self.fonts.keys().filter(|name| name.to_lowercase().contains("emoji")).collect();
+        names.extend(self.fonts.keys().filter(|name| name.to_lowercase().contains("symbol")));
+        names.extend(self.fonts.get_key_value(&self.paint_info.text_info.curr_font_name).map(|(name, _)| name));
+        for name in names {
+            match self.fonts[name].load().regular {
+                Some(font) if text.chars().all(|c| font.glyph(c).id().0 != 0) => {
+                    return render_text_image(&font, &text, STICKER_SIZE as f32, self.paint_info.curr_color);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Put [content] on the image, on top of what is already there, ready to be moved and resized
+    fn start_insert(&mut self, content: DynamicImage) {
+        //Inserting again confirms the image placed before
+        if self.paint_info.insert_info.rect.is_some() {
+            self.commit_insert();
+        }
+        let last_image = self.curr_screenshot.as_ref().unwrap().get_last_image();
+        self.paint_info.insert_info.place(content, last_image);
+        self.render_insert();
+    }
+
+    /// Preview the inserted image where it is placed, on the image without it
+    fn render_insert(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let mut screen_to_paint = self.paint_info.insert_info.original_img.clone();
+        self.paint_info.apply_tool(&mut screen_to_paint, None);
+        //Only the area of the inserted image, where it was and where it is now, has to be shown again
+        let insert_info = &mut self.paint_info.insert_info;
+        let area = insert_info.rect.unwrap_or(Rect::NOTHING).expand(1.);
+        let changed = match insert_info.preview_area.replace(area) {
+            Some(previous) => previous.union(area),
+            None => area,
+        };
+        curr_screenshot.tmp_image = screen_to_paint;
+        curr_screenshot.mark_tmp_changed(changed);
+    }
+
+    /// Stack the inserted image where it is placed, as a single step
+    fn commit_insert(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let mut screen = self.paint_info.insert_info.original_img.clone();
+        let tool = self.paint_info.curr_tool;
+        self.paint_info.curr_tool = Tool::Insert;
+        self.paint_info.apply_tool(&mut screen, None);
+        self.paint_info.curr_tool = tool;
+        if let Some(rect) = self.paint_info.insert_info.rect {
+            screen.add_annotation(rect, &self.paint_info.insert_info.original_img);
+        }
+        curr_screenshot.stack_image(screen.clone());
+        curr_screenshot.set_tmp_image(screen);
+        self.paint_info.insert_info.reset();
+    }
+
+    /// Renders the measured distance, the contrast of the sampled colors and the buttons to add them to the image
+    fn render_measure_window(&mut self, ctx: &Context) {
+        let mut burn_measurement = false;
+        let mut burn_contrast = false;
+        Window::new("Measure").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                let measure_info = &mut self.paint_info.measure_info;
+                ui.checkbox(&mut measure_info.rulers, "Rulers");
+                match measure_info.label() {
+                    Some(label) => {
+                        ui.label(label);
+                        burn_measurement = ui.button("Add distance to image").clicked();
+                    }
+                    None => {
+                        ui.label("Drag between two points to measure them, hold Shift for 45° steps");
+                    }
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    for i in 0..2 {
+                        match measure_info.samples[i] {
+                            Some((color, _)) => {
+                                color_swatch(ui, color);
+                            }
+                            None => {
+                                ui.label("?");
+                            }
+                        }
+                        let picking = measure_info.picking == Some(i);
+                        if ui.selectable_label(picking, if i == 0 { "Pick A" } else { "Pick B" }).clicked() {
+                            measure_info.picking = if picking { None } else { Some(i) };
+                        }
+                    }
+                });
+                match measure_info.contrast() {
+                    Some(ratio) => {
+                        ui.label(RichText::new(format!("Contrast {:.2}:1", ratio)).strong());
+                        ui.label(format!("WCAG: {}", wcag_level(ratio)));
+                        burn_contrast = ui.button("Add contrast to image").clicked();
+                    }
+                    None => {
+                        ui.label("Pick two colors to check their contrast");
+                    }
+                }
+            });
+        if burn_measurement || burn_contrast {
+            let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+            let before = curr_screenshot.get_last_image();
+            let mut screen = before.clone();
+            if burn_measurement {
+                self.paint_info.burn_measurement(&mut screen);
+            } else {
+                self.paint_info.burn_contrast(&mut screen);
+            }
+            let measure_info = &self.paint_info.measure_info;
+            let bounds = match (burn_measurement, measure_info.start, measure_info.samples[1]) {
+                (true, Some(start), _) => Rect::from_two_pos(start, measure_info.end),
+                (false, _, Some((_, pos))) => Rect::from_min_size(pos, Vec2::ZERO),
+                _ => Rect::NOTHING,
+            };
+            screen.add_annotation(bounds, &before);
+            curr_screenshot.stack_image(screen.clone());
+            curr_screenshot.set_tmp_image(screen);
+        }
+    }
+
+    fn render_spotlight_window(&mut self, ctx: &Context) {
+        let spotlight_info = &mut self.paint_info.spotlight_info;
+        //The whole image changes with the settings, so it is drawn again only once a slider is released
+        let mut settings_changed = false;
+        Window::new("Spotlight").collapsible(true).resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut spotlight_info.shape, SpotShape::Rectangle, SpotShape::Rectangle.to_string());
+                    ui.selectable_value(&mut spotlight_info.shape, SpotShape::Ellipse, SpotShape::Ellipse.to_string());
+                });
+                let sliders = [
+                    ui.add(Slider::new(&mut spotlight_info.dim, 0.0..=1.).text("Darken").custom_formatter(|v, _| format!("{:.0}%", v * 100.))),
+                    ui.add(Slider::new(&mut spotlight_info.desaturate, 0.0..=1.).text("Desaturate").custom_formatter(|v, _| format!("{:.0}%", v * 100.))),
+                    ui.add(Slider::new(&mut spotlight_info.feather, 0.0..=60.).text("Soft edge").suffix(" px")),
+                ];
+                settings_changed = sliders.iter().any(|slider| slider.drag_released() || (slider.changed() && !slider.dragged()));
+                if spotlight_info.active {
+                    ui.label("Drag to add areas or to move them, right click removes one.\nPress Enter to confirm, Escape to remove the spotlight");
+                } else {
+                    ui.label("Drag over the areas to keep bright");
+                }
+            });
+        if self.paint_info.spotlight_info.active && settings_changed {
+            self.render_spotlight(None);
+        }
+    }
+
+    /// Preview the spotlight on the image it was started on, drawn again only in [area], or everywhere if None
+    fn render_spotlight(&mut self, area: Option<Rect>) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        match area {
+            Some(area) => {
+                let changed = self.paint_info.redraw_spotlight(&mut curr_screenshot.tmp_image, area);
+                curr_screenshot.mark_tmp_changed(changed);
+            }
+            None => {
+                let mut screen_to_paint = self.paint_info.spotlight_info.original_img.clone();
+                self.paint_info.apply_tool(&mut screen_to_paint, None);
+                curr_screenshot.set_tmp_image(screen_to_paint);
+            }
+        }
+    }
+
+    /// Stack the spotlight as a single step, drawn on the image it was started on
+    fn commit_spotlight(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let mut screen = self.paint_info.spotlight_info.original_img.clone();
+        let tool = self.paint_info.curr_tool;
+        self.paint_info.curr_tool = Tool::Spotlight;
+        self.paint_info.apply_tool(&mut screen, None);
+        self.paint_info.curr_tool = tool;
+        screen.add_annotation(self.paint_info.spotlight_info.bounds(), &self.paint_info.spotlight_info.original_img);
+        curr_screenshot.stack_image(screen.clone());
+        curr_screenshot.set_tmp_image(screen);
+        self.paint_info.spotlight_info.reset();
+    }
+
+    /// Renders the adjustable crop settings: aspect ratio, numeric area and the apply/cancel buttons
+    fn render_crop_window(&mut self, ctx: &Context) {
+        let (width, height) = match &self.curr_screenshot {
+            Some(screenshot) => (screenshot.tmp_image.get_width(), screenshot.tmp_image.get_height()),
+            None => return,
+        };
+        let mut apply = false;
+        let mut cancel = false;
+        //Enter and Escape confirm the values typed in the fields, they apply or discard the crop only when no field is focused.
+        //Checked before the fields are shown, as Enter removes their focus
+        let keys_free = ctx.memory(|m| m.focus().is_none());
+        Window::new("Crop").collapsible(false).resizable(false)
+            .show(ctx, |ui| {
+                let crop_info = &mut self.paint_info.crop_info;
+                ui.checkbox(&mut crop_info.smart, "Smart crop").on_hover_text("Click on a window to crop to its frame");
+                if crop_info.smart {
+                    if crop_info.smart_failed {
+                        ui.label("No window frame found under the pointer");
+                    } else {
+                        ui.label("Click on the window to keep");
+                    }
+                    return;
+                }
+                ui.horizontal(|ui| {
+                    let old_aspect = crop_info.aspect;
+                    ComboBox::from_label("Aspect ratio")
+                        .selected_text(crop_info.aspect.to_string())
+                        .show_ui(ui, |ui| {
+                            for aspect in [AspectRatio::Free, AspectRatio::Square, AspectRatio::Wide, AspectRatio::Standard, AspectRatio::Custom] {
+                                ui.selectable_value(&mut crop_info.aspect, aspect, aspect.to_string());
+                            }
+                        });
+                    let mut ratio_changed = old_aspect != crop_info.aspect;
+                    if crop_info.aspect == AspectRatio::Custom {
+                        ratio_changed |= ui.add(DragValue::new(&mut crop_info.custom_ratio.0).clamp_range(1..=100)).changed();
+                        ui.label(":");
+                        ratio_changed |= ui.add(DragValue::new(&mut crop_info.custom_ratio.1).clamp_range(1..=100)).changed();
+                    }
+                    if ratio_changed {
+                        crop_info.apply_ratio(width, height);
+                    }
+                });
+                match crop_info.get_crop_area(width, height) {
+                    Some((mut x, mut y, mut w, mut h)) => {
+                        let mut changed = false;
+                        ui.horizontal(|ui| {
+                            changed |= ui.add(DragValue::new(&mut x).prefix("x: ").clamp_range(0..=width - 1)).changed();
+                            changed |= ui.add(DragValue::new(&mut y).prefix("y: ").clamp_range(0..=height - 1)).changed();
+                            changed |= ui.add(DragValue::new(&mut w).prefix("w: ").clamp_range(1..=width - x)).changed();
+                            changed |= ui.add(DragValue::new(&mut h).prefix("h: ").clamp_range(1..=height - y)).changed();
+                        });
+                        if changed {
+                            crop_info.rect = Some(Rect::from_min_size(Pos2::new(x as f32, y as f32), Vec2::new(w as f32, h as f32)));
+                            crop_info.apply_ratio(width, height);
+                        }
+                        ui.horizontal(|ui| {
+                            apply = ui.add(Button::new("✔ Apply")).on_hover_text("Crop the image (Enter)").clicked();
+                            cancel = ui.add(Button::new("✖ Cancel")).on_hover_text("Discard the selection (Esc)").clicked();
+                        });
+                    }
+                    None => {
+                        ui.label("Drag on the image to select the area to keep");
+                    }
+                }
+            });
+        if keys_free {
+            apply |= ctx.input(|i| i.key_pressed(Key::Enter));
+            cancel |= ctx.input(|i| i.key_pressed(Key::Escape));
+        }
+        if apply {
+            if let Some((x, y, w, h)) = self.paint_info.crop_info.get_crop_area(width, height) {
+                self.curr_screenshot.as_mut().unwrap().crop(x, y, w, h);
+                self.paint_info.crop_info.reset();
+                self.paint_info.curr_tool = Tool::None;
+            }
+        }
+        if cancel {
+            self.paint_info.crop_info.reset();
+        }
+    }
+
+    /// Renders the zoom selector of the canvas, together with the fit-to-window and 100% buttons
+    fn render_zoom_controls(&mut self, ui: &mut Ui) {
+        ComboBox::from_id_source("zoom")
+            .width(70.0)
+            .selected_text(format!("🔍 {:.0}%", self.view.zoom * 100.))
+            .show_ui(ui, |ui| {
+                for zoom in ZOOM_PRESETS {
+                    let selected = !self.view.fit_to_window && self.view.zoom == zoom;
+                    if ui.selectable_label(selected, format!("{:.0}%", zoom * 100.)).clicked() {
+                        self.view.set_zoom(zoom);
+                    }
+                }
+            }).response.on_hover_text("Zoom (Ctrl + mouse wheel)");
+        if ui.add(Button::new("Fit")).on_hover_text("Fit the image to the window").clicked() {
+            self.view.fit_to_window = true;
+        }
+        if ui.add(Button::new("100%")).on_hover_text("Show the image at its real size").clicked() {
+            self.view.set_zoom(1.0);
+        }
+    }
+
+    /// Renders rotation, flip and resize of the whole image
+    fn render_transform_window(&mut self, ctx: &Context) {
+        let (width, height) = match &self.curr_screenshot {
+            Some(screenshot) => (screenshot.tmp_image.get_width(), screenshot.tmp_image.get_height()),
+            None => return,
+        };
+        let mut transform = None;
+        Window::new("Transform").title_bar(false).resizable(false)
+            .show(ctx, |ui| {
+                ui.group(|ui| {
+                    ui.horizontal(|ui| {
+                        if self.icon_button("arrow-counterclockwise", true, ctx, ui).clicked() {
+                            transform = Some(Transform::Rotate270);
+                        }
+                        if self.icon_button("arrow-clockwise", true, ctx, ui).clicked() {
+                            transform = Some(Transform::Rotate90);
+                        }
+                        if ui.add(Button::new("180°")).on_hover_text("Rotate 180°").clicked() {
+                            transform = Some(Transform::Rotate180);
+                        }
+                        if ui.add(Button::new("⬌")).on_hover_text("Flip horizontally").clicked() {
+                            transform = Some(Transform::FlipHorizontal);
+                        }
+                        if ui.add(Button::new("⬍")).on_hover_text("Flip vertically").clicked() {
+                            transform = Some(Transform::FlipVertical);
+                        }
+                        let close_btn = self.icon_button("x", true, ctx, ui);
+                        if close_btn.clicked() {
+                            self.transform_window_open = false;
+                        }
+                    });
+                });
+                ui.group(|ui| {
+                    let resize_info = &mut self.resize_info;
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Resize ({}x{})", width, height));
+                        ui.radio_value(&mut resize_info.by_percentage, true, "Percentage");
+                        ui.radio_value(&mut resize_info.by_percentage, false, "Size");
+                    });
+                    ui.horizontal(|ui| {
+                        if resize_info.by_percentage {
+                            ui.add(DragValue::new(&mut resize_info.percentage).suffix("%").clamp_range(1..=1000));
+                        } else {
+                            if ui.add(DragValue::new(&mut resize_info.width).prefix("w: ").clamp_range(1..=MAX_RESIZE_SIDE)).changed() {
+                                resize_info.update_aspect(width, height, true);
+                            }
+                            if ui.add(DragValue::new(&mut resize_info.height).prefix("h: ").clamp_range(1..=MAX_RESIZE_SIDE)).changed() {
+                                resize_info.update_aspect(width, height, false);
+                            }
+                            ui.checkbox(&mut resize_info.keep_aspect, "Keep ratio");
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ComboBox::from_label("Filter")
+                            .selected_text(filter_name(resize_info.filter))
+                            .show_ui(ui, |ui| {
+                                for filter in [FilterType::Nearest, FilterType::Triangle, FilterType::CatmullRom, FilterType::Gaussian, FilterType::Lanczos3] {
+                                    ui.selectable_value(&mut resize_info.filter, filter, filter_name(filter));
+                                }
+                            });
+                        let (new_width, new_height) = resize_info.target_size(width, height);
+                        let enabled = new_width != width || new_height != height;
+                        if ui.add_enabled(enabled, Button::new("Resize")).on_hover_text(format!("Resize to {}x{}", new_width, new_height)).clicked() {
+                            transform = Some(Transform::Resize(new_width, new_height, resize_info.filter));
+                        }
+                    });
+                });
+            });
+        if let Some(transform) = transform {
+            self.commit_in_place();
+            let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+            curr_screenshot.transform(transform);
+            self.resize_info.init(curr_screenshot.tmp_image.get_width(), curr_screenshot.tmp_image.get_height());
+        }
+    }
+
     /// Used to restore state of the screenshot when undoing paint changes
     fn _restore_from_paint(&mut self) {
         self.paint_info.reset();
@@ -251,7 +1245,9 @@ impl RustShot {
     }
 
     fn save_paint_changes(&mut self) {
+        self.commit_in_place();
         self.paint_info.reset();
+        self.export_preview = None;
         //Save the changed screenshot as final screenshot
         if self.curr_screenshot.is_some() {
             self.curr_screenshot.as_mut().unwrap().save_changes();
@@ -260,14 +1256,220 @@ impl RustShot {
 
     fn undo_paint_changes(&mut self) {
         self.paint_info.reset();
+        self.paint_info.text_info.reset();
+        self.export_preview = None;
         if self.curr_screenshot.is_some() {
             self.curr_screenshot.as_mut().unwrap().undo_changes();
         }
     }
 
+    /// Image that is saved or copied: the final image, decorated with the frame if enabled
+    fn get_export_image(&mut self) -> Option<DynamicImage> {
+        let mut final_image = self.curr_screenshot.as_ref()?.get_final_image().get_composite();
+        self.watermark_error = None;
+        if let Some(watermark) = self.shortcuts.active_watermark() {
+            let family = match self.fonts.get(&watermark.font) {
+                Some(faces) => faces.load(),
+                None => default_font(),
+            };
+            //The image is still exported, without the watermark
+            match apply_watermark(&final_image, watermark, family.regular.as_ref()) {
+                Ok(watermarked) => final_image = watermarked,
+                Err(err) => self.watermark_error = Some(err.to_string()),
+            }
+        }
+        if self.frame_info.enabled {
+            Some(apply_frame(&final_image, &self.frame_info))
+        } else {
+            Some(final_image)
+        }
+    }
+
+    /// Renders the settings of the frame added around the image when exporting it
+    fn render_frame_window(&mut self, ctx: &Context) {
+        let old_settings = self.frame_info.clone();
+        let frame_info = &mut self.frame_info;
+        Window::new("Frame").collapsible(false).resizable(false)
+            .open(&mut self.frame_window_open)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut frame_info.enabled, "Apply frame when saving and copying");
+                ui.add_enabled_ui(frame_info.enabled, |ui| {
+                    egui::Grid::new("frame settings").num_columns(2).show(ui, |ui| {
+                        ui.label("Margin");
+                        ui.add(Slider::new(&mut frame_info.margin, 0..=200));
+                        ui.end_row();
+                        ui.label("Background");
+                        ui.horizontal(|ui| {
+                            for background in [FrameBackground::Solid, FrameBackground::Transparent, FrameBackground::Gradient] {
+                                ui.radio_value(&mut frame_info.background, background, background.to_string());
+                            }
+                        });
+                        ui.end_row();
+                        if frame_info.background != FrameBackground::Transparent {
+                            ui.label("Background color");
+                            ui.horizontal(|ui| {
+                                ui.color_edit_button_srgba_unmultiplied(&mut frame_info.background_color);
+                                if frame_info.background == FrameBackground::Gradient {
+                                    ui.color_edit_button_srgba_unmultiplied(&mut frame_info.gradient_color);
+                                }
+                            });
+                            ui.end_row();
+                        }
+                        ui.label("Corner radius");
+                        ui.add(Slider::new(&mut frame_info.corner_radius, 0..=60));
+                        ui.end_row();
+                        ui.label("Border");
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut frame_info.border_width, 0..=30));
+                            ui.color_edit_button_srgba_unmultiplied(&mut frame_info.border_color);
+                        });
+                        ui.end_row();
+                        ui.label("Shadow blur");
+                        ui.horizontal(|ui| {
+                            ui.add(Slider::new(&mut frame_info.shadow_blur, 0..=60));
+                            ui.color_edit_button_srgba_unmultiplied(&mut frame_info.shadow_color);
+                        });
+                        ui.end_row();
+                        ui.label("Shadow offset");
+                        ui.horizontal(|ui| {
+                            ui.add(DragValue::new(&mut frame_info.shadow_offset.0).prefix("x: ").clamp_range(-50..=50));
+                            ui.add(DragValue::new(&mut frame_info.shadow_offset.1).prefix("y: ").clamp_range(-50..=50));
+                        });
+                        ui.end_row();
+                    });
+                });
+            });
+        //Compute again the preview only when something changed (avoid blurring the shadow at every frame)
+        if old_settings != self.frame_info {
+            self.export_preview = None;
+        }
+    }
+
+    /// Renders the watermark profiles and the settings of the selected one
+    fn render_watermark_window(&mut self, ctx: &Context) {
+        let old_settings = (self.shortcuts.watermarks.clone(), self.shortcuts.watermark_profile, self.shortcuts.watermark_enabled);
+        let shortcuts = &mut self.shortcuts;
+        let fonts = &self.fonts;
+        Window::new("Watermark").collapsible(false).resizable(false)
+            .open(&mut self.watermark_window_open)
+            .show(ctx, |ui| {
+                ui.checkbox(&mut shortcuts.watermark_enabled, "Apply watermark when saving and copying");
+                ui.horizontal(|ui| {
+                    let selected = shortcuts.watermarks.get(shortcuts.watermark_profile).map(|w| w.name.clone()).unwrap_or("None".to_string());
+                    ComboBox::from_label("Profile")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            for (i, watermark) in shortcuts.watermarks.iter().enumerate() {
+                                ui.selectable_value(&mut shortcuts.watermark_profile, i, watermark.name.clone());
+                            }
+                        });
+                    if ui.button("New").clicked() {
+                        let name = format!("Profile {}", shortcuts.watermarks.len() + 1);
+                        shortcuts.watermarks.push(WatermarkSettings::new(&name));
+                        shortcuts.watermark_profile = shortcuts.watermarks.len() - 1;
+                    }
+                    if shortcuts.watermark_profile < shortcuts.watermarks.len() && ui.button("Delete").clicked() {
+                        shortcuts.watermarks.remove(shortcuts.watermark_profile);
+                        shortcuts.watermark_profile = 0;
+                    }
+                });
+                let watermark = match shortcuts.watermarks.get_mut(shortcuts.watermark_profile) {
+                    Some(watermark) => watermark,
+                    None => {
+                        ui.label("Create a profile to set up a watermark");
+                        return;
+                    }
+                };
+                egui::Grid::new("watermark settings").num_columns(2).show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut watermark.name);
+                    ui.end_row();
+                    ui.label("Type");
+                    ui.horizontal(|ui| {
+                        for kind in [WatermarkKind::Text, WatermarkKind::Logo] {
+                            ui.radio_value(&mut watermark.kind, kind, kind.to_string());
+                        }
+                    });
+                    ui.end_row();
+                    match watermark.kind {
+                        WatermarkKind::Text => {
+                            ui.label("Text");
+                            ui.text_edit_singleline(&mut watermark.text);
+                            ui.end_row();
+                            ui.label("Font");
+                            ui.horizontal(|ui| {
+                                let font_name = if fonts.contains_key(&watermark.font) { watermark.font.clone() } else { DEFAULT_FONT.to_string() };
+                                ComboBox::from_id_source("watermark font")
+                                    .selected_text(font_name)
+                                    .show_ui(ui, |ui| {
+                                        ScrollArea::vertical().max_height(250.).show(ui, |ui| {
+                                            for name in fonts.keys() {
+                                                ui.selectable_value(&mut watermark.font, name.clone(), name);
+                                            }
+                                        });
+                                    });
+                                ui.add(DragValue::new(&mut watermark.font_size).clamp_range(8..=300).suffix(" px"));
+                                ui.color_edit_button_srgba_unmultiplied(&mut watermark.color);
+                            });
+                            ui.end_row();
+                        }
+                        WatermarkKind::Logo => {
+                            ui.label("Logo");
+                            ui.horizontal(|ui| {
+                                let file_name = watermark.logo_path.as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().to_string());
+                                ui.label(file_name.unwrap_or("None".to_string()));
+                                if ui.button("Choose...").clicked() {
+                                    if let Some(path) = FileDialog::new().add_filter("Images", &["png", "jpg", "jpeg", "gif", "bmp", "webp"]).pick_file() {
+                                        watermark.logo_path = Some(path);
+                                    }
+                                }
+                            });
+                            ui.end_row();
+                            ui.label("Size");
+                            ui.add(Slider::new(&mut watermark.logo_scale, 0.02..=1.).custom_formatter(|v, _| format!("{:.0}% of the width", v * 100.)));
+                            ui.end_row();
+                        }
+                    }
+                    ui.label("Opacity");
+                    ui.add(Slider::new(&mut watermark.opacity, 0.05..=1.).custom_formatter(|v, _| format!("{:.0}%", v * 100.)));
+                    ui.end_row();
+                    ui.label("Tiled");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut watermark.tiled, "");
+                        if watermark.tiled {
+                            ui.add(DragValue::new(&mut watermark.spacing).clamp_range(0..=500).prefix("spacing: ").suffix(" px"));
+                        }
+                    });
+                    ui.end_row();
+                    if !watermark.tiled {
+                        ui.label("Position");
+                        ComboBox::from_id_source("watermark position")
+                            .selected_text(watermark.position.to_string())
+                            .show_ui(ui, |ui| {
+                                for position in [WatermarkPosition::TopLeft, WatermarkPosition::TopRight, WatermarkPosition::BottomLeft, WatermarkPosition::BottomRight, WatermarkPosition::Center] {
+                                    ui.selectable_value(&mut watermark.position, position, position.to_string());
+                                }
+                            });
+                        ui.end_row();
+                        ui.label("Margin");
+                        ui.add(Slider::new(&mut watermark.margin, 0..=200));
+                        ui.end_row();
+                    }
+                });
+                if let Some(error) = &self.watermark_error {
+                    ui.label(RichText::new(error).color(Color32::RED));
+                }
+            });
+        if old_settings != (self.shortcuts.watermarks.clone(), self.shortcuts.watermark_profile, self.shortcuts.watermark_enabled) {
+            self.export_preview = None;
+            self.watermark_error = None;
+            self.watermark_dirty = true;
+        }
+    }
+
     fn copy_image(&mut self) {
         let mut clipboard = Clipboard::new().unwrap();
-        let final_image = self.curr_screenshot.as_ref().unwrap().get_final_image().get_image();
+        let final_image = self.get_export_image().unwrap();
         let bytes = final_image.as_bytes();
         let img = arboard::ImageData {
             width: final_image.width() as usize,
@@ -345,12 +1547,51 @@ impl RustShot {
         if self.shape_window_open {
             self.render_shape_window(ctx, ui);
         }
+        if self.transform_window_open {
+            self.render_transform_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Text {
+            self.render_text_style_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Magnifier {
+            self.render_magnifier_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Arrow {
+            self.render_arrow_style_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Callout {
+            self.render_callout_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Insert {
+            self.render_insert_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Measure {
+            self.render_measure_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Spotlight {
+            self.render_spotlight_window(ctx);
+        }
+        if self.color_window_open || self.paint_info.curr_tool == Tool::Eyedropper {
+            self.render_color_window(ctx);
+        }
+        if self.layers_window_open {
+            self.render_layers_window(ctx);
+        }
+        if self.adjust_window_open {
+            self.render_adjustments_window(ctx);
+        }
+        if self.paint_info.curr_tool == Tool::Crop {
+            self.render_crop_window(ctx);
+        } else {
+            self.paint_info.crop_info.reset();
+        }
         ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
             let undo_changes_btn = ui.add(Button::new("Exit"));
             let save_paint_btn = ui.add(Button::new("Save changes"));
             //let save_paint_btn = ui.add_sized([100.0, 100.0],Button::new("Save changes"));
+            self.render_zoom_controls(ui);
 
-            if self.curr_screenshot.as_ref().unwrap().get_images_len() > 1 && self.paint_info.curr_tool != Tool::Text {
+            if self.curr_screenshot.as_ref().unwrap().get_images_len() > 1 && !self.paint_info.editing_in_place() {
                 let undo_btn = self.icon_button("arrow-90deg-left", true, ctx, ui);
                 if undo_btn.clicked() {
                     let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
@@ -362,7 +1603,7 @@ impl RustShot {
             } else {
                 let _undo_btn = self.icon_button("arrow-90deg-left", false, ctx, ui);
             }
-            if self.curr_screenshot.as_ref().unwrap().get_redo_images_len() > 0 && self.paint_info.curr_tool != Tool::Text {
+            if self.curr_screenshot.as_ref().unwrap().get_redo_images_len() > 0 && !self.paint_info.editing_in_place() {
                 let redo_btn = self.icon_button("arrow-90deg-right", true, ctx, ui);
                 if redo_btn.clicked() {
                     let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
@@ -375,10 +1616,28 @@ impl RustShot {
             }
             let draw_btn = self.icon_button("pencil-fill", true, ctx, ui);
             let text_btn = self.icon_button("fonts", true, ctx, ui);
+            let callout_btn = self.icon_button("chat-left-text", true, ctx, ui);
+            let magnifier_btn = self.icon_button("zoom-in", true, ctx, ui);
+            let insert_btn = self.icon_button("image", true, ctx, ui);
+            let measure_btn = self.icon_button("rulers", true, ctx, ui);
+            let spotlight_btn = self.icon_button("spotlight", true, ctx, ui);
             let highlighter_btn = self.icon_button("highlighter-solid", true, ctx, ui);
             let shape_btn = self.icon_button("pentagon", true, ctx, ui);
             let crop_btn = self.icon_button("crop", true, ctx, ui);
+            let trim_btn = self.icon_button("fullscreen-exit", true, ctx, ui);
+            if self.nothing_to_trim {
+                if trim_btn.hovered() {
+                    ui.label("Nothing to trim");
+                } else {
+                    self.nothing_to_trim = false;
+                }
+            }
+            let transform_btn = self.icon_button("bounding-box-circles", true, ctx, ui);
             let eraser_btn = self.icon_button("eraser-fill", true, ctx, ui);
+            let eyedropper_btn = self.icon_button("eyedropper", true, ctx, ui);
+            let layers_btn = self.icon_button("layers", true, ctx, ui);
+            let capture_adjustments = self.curr_screenshot.as_ref().unwrap().tmp_image.get_adjustments();
+            let adjust_btn = self.icon_button("sliders", capture_adjustments.is_some() && !self.adjust_window_open, ctx, ui);
             let rmv_tool_btn = self.icon_button("x-octagon", true, ctx, ui);
             ui.label("Current tool:");
             let _curr_tool = match self.paint_info.curr_tool {
@@ -387,34 +1646,72 @@ impl RustShot {
                 Tool::FilledRect => self.icon("square-fill", ctx, ui),
                 Tool::HollowCircle => self.icon("circle", ctx, ui),
                 Tool::FilledCircle => self.icon("circle-fill", ctx, ui),
+                Tool::HollowEllipse => self.icon("ellipse", ctx, ui),
+                Tool::FilledEllipse => self.icon("ellipse-fill", ctx, ui),
                 Tool::Arrow => self.icon("arrow-up-right", ctx, ui),
                 Tool::Eraser => self.icon("eraser-fill", ctx, ui),
                 Tool::Highlighter => self.icon("highlighter-solid", ctx, ui),
                 Tool::Crop => self.icon("crop", ctx, ui),
                 Tool::Text => self.icon("fonts", ctx, ui),
+                Tool::Callout => self.icon("chat-left-text", ctx, ui),
+                Tool::Magnifier => self.icon("zoom-in", ctx, ui),
+                Tool::Eyedropper => self.icon("eyedropper", ctx, ui),
+                Tool::Insert => self.icon("image", ctx, ui),
+                Tool::Measure => self.icon("rulers", ctx, ui),
+                Tool::Spotlight => self.icon("spotlight", ctx, ui),
                 Tool::None => ui.add(Label::new("None")),
             };
             if self.paint_info.curr_tool != Tool::None && self.paint_info.curr_tool != Tool::Crop && self.paint_info.curr_tool != Tool::Eraser {
                 ui.color_edit_button_srgba_unmultiplied(&mut self.paint_info.curr_color);
+                if ui.button("Colors").on_hover_text("Recent colors and palettes").clicked() {
+                    self.color_window_open = !self.color_window_open;
+                }
             }
-            if self.paint_info.curr_tool != Tool::None && self.paint_info.curr_tool != Tool::Crop && self.paint_info.curr_tool != Tool::Text {
+            if self.paint_info.curr_tool != Tool::None && self.paint_info.curr_tool != Tool::Crop && self.paint_info.curr_tool != Tool::Text && self.paint_info.curr_tool != Tool::Eyedropper && self.paint_info.curr_tool != Tool::Insert && self.paint_info.curr_tool != Tool::Measure && self.paint_info.curr_tool != Tool::Spotlight {
                 ui.add(Slider::new(&mut self.paint_info.curr_thickness, 0..=30));
             }
+            if self.paint_info.curr_tool == Tool::Drawing {
+                ui.checkbox(&mut self.paint_info.pen.smoothing, "Smooth").on_hover_text("Join the points of the stroke with a curve");
+                ui.checkbox(&mut self.paint_info.pen.speed_width, "Speed width").on_hover_text("Draw thinner lines when moving fast");
+            }
+            if self.paint_info.curr_tool == Tool::Eraser {
+                ui.selectable_value(&mut self.paint_info.eraser_mode, EraserMode::Pixels, EraserMode::Pixels.to_string()).on_hover_text("Restore the original image under the brush");
+                ui.selectable_value(&mut self.paint_info.eraser_mode, EraserMode::Objects, EraserMode::Objects.to_string()).on_hover_text("Remove the whole annotations touched by the brush");
+            }
+            if self.paint_info.curr_tool == Tool::Highlighter {
+                ui.add(Slider::new(&mut self.paint_info.highlighter_opacity, 0.05..=1.).text("Opacity").custom_formatter(|v, _| format!("{:.0}%", v * 100.)));
+            }
+            if self.paint_info.curr_tool.is_shape() {
+                let snap = &mut self.paint_info.snap;
+                ui.checkbox(&mut snap.grid, "Grid").on_hover_text("Snap to a pixel grid");
+                if snap.grid {
+                    ui.add(DragValue::new(&mut snap.grid_size).clamp_range(2..=200).suffix(" px"));
+                }
+                ui.checkbox(&mut snap.guides, "Guides").on_hover_text("Snap to other annotations and to the edges in the image.\nHold Shift for squares and 45° lines, Alt to draw from the center");
+            }
             else if self.paint_info.curr_tool == Tool::Text {
                 ui.add(Slider::new(&mut self.paint_info.text_info.curr_dim, 0..=60));
+                let mut chosen = None;
                 ComboBox::from_label("Font")
                     .selected_text(self.paint_info.text_info.curr_font_name.clone())
                     .show_ui(ui, |ui| {
                         ui.style_mut().wrap = Some(false);
-                        ui.set_min_width(60.0);
-                        for s in self.fonts.keys() {
-                            ui.selectable_value(&mut self.paint_info.text_info.curr_font_name, s.clone(), s);
-                        }
+                        ui.set_min_width(160.0);
+                        ui.add(TextEdit::singleline(&mut self.font_filter).hint_text("Search"));
+                        let filter = self.font_filter.to_lowercase();
+                        ScrollArea::vertical().max_height(250.).show(ui, |ui| {
+                            for name in self.fonts.keys().filter(|name| name.to_lowercase().contains(&filter)) {
+                                if ui.selectable_label(*name == self.paint_info.text_info.curr_font_name, name).clicked() {
+                                    chosen = Some(name.clone());
+                                }
+                            }
+                        });
                     });
-                //If the font was not correctly loaded, keep the old one to avoid panic
-                self.paint_info.text_info.curr_font = match self.fonts.get(self.paint_info.text_info.curr_font_name.as_str()) {
-                    Some(font) => font.clone(),
-                    None => self.paint_info.text_info.curr_font.clone(),
+                if let Some(name) = chosen {
+                    if self.select_font(&name) {
+                        self.shortcuts.remember_font(&name);
+                        self.pending_font = None;
+                    }
                 }
             }
             if rmv_tool_btn.clicked() {
@@ -433,10 +1730,28 @@ impl RustShot {
             }
             if text_btn.clicked() {
                 //Go to text mode only if the default font has been loaded correctly
-                if self.paint_info.text_info.curr_font.is_some() {
+                if self.paint_info.text_info.has_font() {
                     self.paint_info.curr_tool = Tool::Text;
                 }
             }
+            if callout_btn.clicked() {
+                //The callout text uses the font of the text tool
+                if self.paint_info.text_info.has_font() {
+                    self.paint_info.curr_tool = Tool::Callout;
+                }
+            }
+            if magnifier_btn.clicked() {
+                self.paint_info.curr_tool = Tool::Magnifier;
+            }
+            if insert_btn.clicked() {
+                self.paint_info.curr_tool = Tool::Insert;
+            }
+            if measure_btn.clicked() {
+                self.paint_info.curr_tool = Tool::Measure;
+            }
+            if spotlight_btn.clicked() {
+                self.paint_info.curr_tool = Tool::Spotlight;
+            }
             if shape_btn.clicked() {
                 self.shape_window_open = true;
             }
@@ -446,36 +1761,523 @@ impl RustShot {
             if crop_btn.clicked() {
                 self.paint_info.curr_tool = Tool::Crop;
             }
+            if trim_btn.clicked() {
+                self.commit_in_place();
+                self.nothing_to_trim = !self.curr_screenshot.as_mut().unwrap().auto_trim();
+            }
+            if transform_btn.clicked() {
+                let curr_screenshot = self.curr_screenshot.as_ref().unwrap();
+                self.resize_info.init(curr_screenshot.tmp_image.get_width(), curr_screenshot.tmp_image.get_height());
+                self.transform_window_open = true;
+            }
             if eraser_btn.clicked() {
                 self.paint_info.curr_tool = Tool::Eraser;
             }
+            if eyedropper_btn.clicked() && self.paint_info.curr_tool != Tool::Eyedropper {
+                self.paint_info.colors.previous_tool = self.paint_info.curr_tool;
+                self.paint_info.curr_tool = Tool::Eyedropper;
+            }
+            if layers_btn.clicked() {
+                self.layers_window_open = !self.layers_window_open;
+            }
+            if adjust_btn.clicked() {
+                self.adjustments = capture_adjustments.unwrap();
+                self.adjust_window_open = true;
+            }
+        });
+    }
+
+    /// Stack the text that was being written as a single step, drawn without the cursor and the textarea
+    fn commit_text(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let text_info = &self.paint_info.text_info;
+        if text_info.buffer.is_empty() {
+            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+        } else {
+            let mut screen = text_info.original_img.clone();
+            let tool = self.paint_info.curr_tool;
+            self.paint_info.curr_tool = Tool::Text;
+            self.paint_info.apply_tool(&mut screen, None);
+            self.paint_info.curr_tool = tool;
+            let text_info = &self.paint_info.text_info;
+            screen.add_annotation(Rect::from_min_size(text_info.edge, Vec2::new(text_info.width, text_info.height)), &text_info.original_img);
+            curr_screenshot.stack_image(screen.clone());
+            curr_screenshot.set_tmp_image(screen);
+        }
+        self.paint_info.text_info.reset();
+    }
+
+    /// Stack the callout being edited as a single step, drawn without the cursor and the handle of the tail
+    fn commit_callout(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let mut screen = self.paint_info.callout_info.original_img.clone();
+        let tool = self.paint_info.curr_tool;
+        self.paint_info.curr_tool = Tool::Callout;
+        self.paint_info.apply_tool(&mut screen, None);
+        self.paint_info.curr_tool = tool;
+        if let Some(bubble) = self.paint_info.callout_box() {
+            screen.add_annotation(bubble, &self.paint_info.callout_info.original_img);
+        }
+        curr_screenshot.stack_image(screen.clone());
+        curr_screenshot.set_tmp_image(screen);
+        self.paint_info.callout_info.reset();
+    }
+
+    /// Stack what the tools editing the image in place are showing, before a change made on the last stacked image
+    fn commit_in_place(&mut self) {
+        if self.paint_info.text_info.dirty {
+            self.commit_text();
+        }
+        if self.paint_info.callout_info.rect.is_some() {
+            self.commit_callout();
+        }
+        if self.paint_info.magnifier_info.source.is_some() {
+            self.commit_magnifier();
+        }
+        if self.paint_info.insert_info.rect.is_some() {
+            self.commit_insert();
+        }
+        if self.paint_info.spotlight_info.active {
+            self.commit_spotlight();
+        }
+    }
+
+    /// Logic of the callout tool: create the box, move box and tail, and type its text.
+    /// The callout is only previewed on the image until it is completed
+    fn callout_logic(&mut self, img: Response, ui: &mut Ui) {
+        let zoom = self.view.zoom;
+        let tolerance = CROP_HANDLE_SIZE / zoom;
+        let bubble = self.paint_info.callout_box().unwrap_or(Rect::NOTHING);
+        if img.drag_started_by(PointerButton::Primary) || img.clicked() {
+            //When the click is detected the pointer has already been released, so it has no press origin
+            let origin = if img.clicked() { img.interact_pointer_pos() } else { ui.input(|i| i.pointer.press_origin()) };
+            if let Some(pos) = origin {
+                let ptr = into_relative_pos(pos, img.rect, zoom);
+                let target = self.paint_info.callout_info.hit(ptr, bubble, tolerance);
+                if target == CalloutDrag::New {
+                    //Pressing outside the current callout completes it and starts a new one
+                    if self.paint_info.callout_info.rect.is_some() {
+                        self.commit_callout();
+                    }
+                    let last_image = self.curr_screenshot.as_ref().unwrap().get_last_image();
+                    self.paint_info.callout_info.create(ptr, last_image);
+                }
+                if img.drag_started_by(PointerButton::Primary) {
+                    self.paint_info.callout_info.start_drag(ptr, target);
+                }
+            }
+        }
+        let callout_info = &mut self.paint_info.callout_info;
+        if img.dragged_by(PointerButton::Primary) {
+            if let Some(pos) = img.interact_pointer_pos() {
+                callout_info.drag(into_relative_pos(pos, img.rect, zoom));
+            }
+        } else if img.drag_released_by(PointerButton::Primary) {
+            callout_info.end_drag();
+        }
+        if let Some(pos) = img.hover_pos() {
+            let cursor = match callout_info.dragging.unwrap_or(callout_info.hit(into_relative_pos(pos, img.rect, zoom), bubble, tolerance)) {
+                CalloutDrag::Tail => CursorIcon::Grab,
+                CalloutDrag::Box => CursorIcon::Text,
+                CalloutDrag::New => CursorIcon::Crosshair,
+            };
+            ui.ctx().set_cursor_icon(cursor);
+        }
+        if callout_info.rect.is_none() {
+            return;
+        }
+        // I need to keep updating to iterate over the events
+        ui.ctx().request_repaint();
+        let mut copied_text = None;
+        let mut exit = false;
+        ui.input(|i| {
+            for e in &i.events {
+                match callout_info.buffer.handle_event(e) {
+                    TextAction::Copy(text) => copied_text = Some(text),
+                    TextAction::Exit => exit = true,
+                    TextAction::None => {}
+                }
+            }
         });
+        if let Some(text) = copied_text {
+            ui.output_mut(|o| o.copied_text = text);
+        }
+        if exit {
+            self.commit_callout();
+            return;
+        }
+        //Draw the callout and its cursor on the image without it, only as a preview
+        let mut screen_to_paint = self.paint_info.callout_info.original_img.clone();
+        self.paint_info.apply_tool(&mut screen_to_paint, None);
+        self.paint_info.draw_callout_cursor(&mut screen_to_paint);
+        //Only the area of the callout, where it was and where it is now, has to be shown again
+        let tail = self.paint_info.callout_info.tail;
+        let area = self.paint_info.callout_box().unwrap_or(Rect::NOTHING).union(Rect::from_min_max(tail, tail)).expand(self.paint_info.curr_thickness as f32 + 2.);
+        let changed = match self.paint_info.callout_info.preview_area.replace(area) {
+            Some(previous) => previous.union(area),
+            None => area,
+        };
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        curr_screenshot.tmp_image = screen_to_paint;
+        curr_screenshot.mark_tmp_changed(changed);
+        //Handle to drag the tail
+        let painter = ui.painter_at(img.rect);
+        let tail = into_absolute_pos(tail, img.rect, zoom);
+        painter.circle_filled(tail, CROP_HANDLE_SIZE / 2., Color32::WHITE);
+        painter.circle_stroke(tail, CROP_HANDLE_SIZE / 2., Stroke::new(1., Color32::BLACK));
+    }
+
+    /// Stack the magnifier being edited as a single step. One still being drawn is dropped
+    fn commit_magnifier(&mut self) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        if self.paint_info.magnifier_info.dragging == Some(MagnifierDrag::New) {
+            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+            self.paint_info.magnifier_info.reset();
+            return;
+        }
+        let mut screen = self.paint_info.magnifier_info.original_img.clone();
+        let tool = self.paint_info.curr_tool;
+        self.paint_info.curr_tool = Tool::Magnifier;
+        self.paint_info.apply_tool(&mut screen, None);
+        self.paint_info.curr_tool = tool;
+        let magnifier_info = &self.paint_info.magnifier_info;
+        screen.add_annotation(Rect::from_center_size(magnifier_info.inset_center, magnifier_info.inset_half_size() * 2.), &magnifier_info.original_img);
+        curr_screenshot.stack_image(screen.clone());
+        curr_screenshot.set_tmp_image(screen);
+        self.paint_info.magnifier_info.reset();
+    }
+
+    /// Logic of the magnifier tool: select the region to enlarge, then move it and its inset until confirmed.
+    /// The magnifier is only previewed on the image until then
+    fn magnifier_logic(&mut self, img: Response, ui: &mut Ui) {
+        let zoom = self.view.zoom;
+        let mut changed = false;
+        if img.drag_started_by(PointerButton::Primary) {
+            if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                let ptr = into_relative_pos(pos, img.rect, zoom);
+                match self.paint_info.magnifier_info.hit(ptr) {
+                    MagnifierDrag::New => {
+                        //Pressing outside the current magnifier completes it and starts a new one
+                        if self.paint_info.magnifier_info.source.is_some() {
+                            self.commit_magnifier();
+                        }
+                        let last_image = self.curr_screenshot.as_ref().unwrap().get_last_image();
+                        self.paint_info.magnifier_info.create(ptr, last_image);
+                    }
+                    target => self.paint_info.magnifier_info.start_drag(ptr, target),
+                }
+            }
+        }
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let magnifier_info = &mut self.paint_info.magnifier_info;
+        if img.dragged_by(PointerButton::Primary) {
+            if let Some(pos) = img.interact_pointer_pos() {
+                magnifier_info.drag(into_relative_pos(pos, img.rect, zoom));
+                changed = true;
+            }
+        } else if img.drag_released_by(PointerButton::Primary) {
+            magnifier_info.end_drag(curr_screenshot.tmp_image.get_width(), curr_screenshot.tmp_image.get_height());
+            changed = true;
+        }
+        if let Some(pos) = img.hover_pos() {
+            let cursor = match magnifier_info.dragging.unwrap_or(magnifier_info.hit(into_relative_pos(pos, img.rect, zoom))) {
+                MagnifierDrag::New => CursorIcon::Crosshair,
+                _ => CursorIcon::Move,
+            };
+            ui.ctx().set_cursor_icon(cursor);
+        }
+        if magnifier_info.source.is_none() {
+            return;
+        }
+        //While the source region is being drawn, only its outline is shown
+        if magnifier_info.dragging == Some(MagnifierDrag::New) {
+            if let Some(source) = magnifier_info.source {
+                let painter = ui.painter_at(img.rect);
+                let screen_rect = Rect::from_min_max(into_absolute_pos(source.min, img.rect, zoom), into_absolute_pos(source.max, img.rect, zoom));
+                painter.rect_stroke(screen_rect, 0., Stroke::new(1., Color32::WHITE));
+            }
+            return;
+        }
+        if ui.input(|i| i.key_pressed(Key::Enter) || i.key_pressed(Key::Escape)) {
+            self.commit_magnifier();
+            return;
+        }
+        //The preview is drawn again only when something changes, settings in the window included
+        if changed || ui.input(|i| i.pointer.any_down()) {
+            let mut screen_to_paint = self.paint_info.magnifier_info.original_img.clone();
+            self.paint_info.apply_tool(&mut screen_to_paint, None);
+            //Only the area of the magnifier, where it was and where it is now, has to be shown again
+            let magnifier_info = &mut self.paint_info.magnifier_info;
+            let area = magnifier_info.area(self.paint_info.curr_thickness);
+            let changed = match magnifier_info.preview_area.replace(area) {
+                Some(previous) => previous.union(area),
+                None => area,
+            };
+            curr_screenshot.tmp_image = screen_to_paint;
+            curr_screenshot.mark_tmp_changed(changed);
+        }
+    }
+
+    /// Logic of the eyedropper: preview the color under the pointer and pick it with a click
+    fn eyedropper_logic(&mut self, img: Response, ui: &mut Ui) {
+        let curr_screenshot = self.curr_screenshot.as_ref().unwrap();
+        let zoom = self.view.zoom;
+        let colors = &mut self.paint_info.colors;
+        if let Some(pos) = img.hover_pos() {
+            let color = curr_screenshot.tmp_image.sample(into_relative_pos(pos, img.rect, zoom), colors.sample_size);
+            //Swatch next to the pointer, with the hex code of the color
+            let painter = ui.painter_at(img.rect);
+            let swatch = Rect::from_min_size(pos + Vec2::new(16., 16.), Vec2::splat(28.));
+            painter.rect_filled(swatch, 4., Color32::from_rgba_unmultiplied(color[0], color[1], color[2], color[3]));
+            painter.rect_stroke(swatch, 4., Stroke::new(1., Color32::WHITE));
+            painter.text(swatch.right_center() + Vec2::new(6., 0.), Align2::LEFT_CENTER, color_to_hex(color), FontId::monospace(14.), Color32::WHITE);
+            ui.ctx().set_cursor_icon(CursorIcon::Crosshair);
+        }
+        if img.clicked_by(PointerButton::Primary) {
+            if let Some(pos) = img.interact_pointer_pos() {
+                let color = curr_screenshot.tmp_image.sample(into_relative_pos(pos, img.rect, zoom), colors.sample_size);
+                self.paint_info.curr_color = color;
+                colors.add_recent(color);
+                //Go back to the tool the color was picked for
+                self.paint_info.curr_tool = colors.previous_tool;
+            }
+        }
+    }
+
+    /// Logic of the measure tool: drag to measure, click to sample a color when picking, and the rulers along the edges
+    fn measure_logic(&mut self, img: Response, ui: &mut Ui) {
+        let curr_screenshot = self.curr_screenshot.as_ref().unwrap();
+        let zoom = self.view.zoom;
+        let measure_info = &mut self.paint_info.measure_info;
+        //Measures are between whole pixels
+        let to_pixel = |pos: Pos2| into_relative_pos(pos, img.rect, zoom).floor();
+        match measure_info.picking {
+            Some(i) => {
+                if img.clicked_by(PointerButton::Primary) {
+                    if let Some(pos) = img.interact_pointer_pos() {
+                        let ptr = to_pixel(pos);
+                        measure_info.samples[i] = Some((curr_screenshot.tmp_image.sample(ptr, 1), ptr));
+                        measure_info.picking = None;
+                    }
+                }
+            }
+            None => {
+                if img.drag_started_by(PointerButton::Primary) {
+                    if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                        measure_info.start = Some(to_pixel(pos));
+                        measure_info.end = to_pixel(pos);
+                        measure_info.dragging = true;
+                    }
+                }
+                if img.dragged_by(PointerButton::Primary) && measure_info.dragging {
+                    if let (Some(pos), Some(start)) = (img.interact_pointer_pos(), measure_info.start) {
+                        let mut end = to_pixel(pos);
+                        //Shift keeps the line at multiples of 45°
+                        if ui.input(|i| i.modifiers.shift) {
+                            let delta = end - start;
+                            let angle = (delta.y.atan2(delta.x) / std::f32::consts::FRAC_PI_4).round() * std::f32::consts::FRAC_PI_4;
+                            end = (start + Vec2::angled(angle) * delta.length()).round();
+                        }
+                        measure_info.end = end;
+                    }
+                } else if img.drag_released_by(PointerButton::Primary) {
+                    measure_info.dragging = false;
+                }
+            }
+        }
+        if img.hovered() {
+            ui.ctx().set_cursor_icon(CursorIcon::Crosshair);
+        }
+        let painter = ui.painter_at(img.rect);
+        let to_screen = |pos: Pos2| into_absolute_pos(pos + Vec2::splat(0.5), img.rect, zoom);
+        //Measured line, with its length next to the pointer
+        if let (Some(start), Some(label)) = (measure_info.start, measure_info.label()) {
+            let (start, end) = (to_screen(start), to_screen(measure_info.end));
+            painter.line_segment([start, end], Stroke::new(2., Color32::BLACK));
+            painter.line_segment([start, end], Stroke::new(1., Color32::from_rgb(255, 230, 0)));
+            painter.circle_filled(start, 3., Color32::from_rgb(255, 230, 0));
+            painter.circle_filled(end, 3., Color32::from_rgb(255, 230, 0));
+            let text_pos = end + Vec2::new(12., 12.);
+            let galley = painter.layout_no_wrap(label, FontId::monospace(13.), Color32::WHITE);
+            painter.rect_filled(Rect::from_min_size(text_pos, galley.size()).expand(4.), 3., Color32::from_black_alpha(180));
+            painter.galley(text_pos, galley);
+        }
+        for (i, sample) in measure_info.samples.iter().enumerate() {
+            if let Some((_, pos)) = sample {
+                let center = to_screen(*pos);
+                painter.circle_stroke(center, 5., Stroke::new(1.5, Color32::WHITE));
+                painter.text(center + Vec2::new(8., -8.), Align2::LEFT_BOTTOM, if i == 0 { "A" } else { "B" }, FontId::proportional(13.), Color32::WHITE);
+            }
+        }
+        if measure_info.rulers {
+            draw_rulers(&painter, img.rect.intersect(ui.clip_rect()), img.rect, zoom, img.hover_pos());
+        }
+    }
+
+    /// Logic of the spotlight: drag to add an area or to move one, right click removes it.
+    /// Enter confirms the spotlight and Escape removes it. Only the part of the image around the areas changed is drawn again
+    fn spotlight_logic(&mut self, img: Response, ui: &mut Ui) {
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let zoom = self.view.zoom;
+        let spotlight_info = &mut self.paint_info.spotlight_info;
+        let mut started = false;
+        if img.drag_started_by(PointerButton::Primary) {
+            if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                //The first area starts the spotlight, on top of the current image
+                if !spotlight_info.active {
+                    spotlight_info.original_img = curr_screenshot.get_last_image();
+                    spotlight_info.active = true;
+                    started = true;
+                }
+                spotlight_info.start_drag(into_relative_pos(pos, img.rect, zoom));
+            }
+        }
+        if img.dragged_by(PointerButton::Primary) {
+            if let Some(pos) = img.interact_pointer_pos() {
+                spotlight_info.drag(into_relative_pos(pos, img.rect, zoom));
+            }
+        } else if img.drag_released_by(PointerButton::Primary) {
+            spotlight_info.end_drag();
+        }
+        if img.secondary_clicked() {
+            if let Some(index) = img.interact_pointer_pos().and_then(|pos| spotlight_info.hit(into_relative_pos(pos, img.rect, zoom))) {
+                spotlight_info.remove(index);
+            }
+        }
+        if let Some(pos) = img.hover_pos() {
+            let cursor = match spotlight_info.hit(into_relative_pos(pos, img.rect, zoom)) {
+                Some(_) => CursorIcon::Move,
+                None => CursorIcon::Crosshair,
+            };
+            ui.ctx().set_cursor_icon(cursor);
+        }
+        let (confirm, cancel) = ui.input(|i| (i.key_pressed(Key::Enter), i.key_pressed(Key::Escape)));
+        //Without areas left there is no spotlight anymore
+        if spotlight_info.active && (cancel || (spotlight_info.areas.is_empty() && spotlight_info.dragging.is_none())) {
+            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+            spotlight_info.reset();
+            return;
+        }
+        if confirm && spotlight_info.active {
+            self.commit_spotlight();
+            return;
+        }
+        //The whole image is dimmed when the spotlight starts, then only around the areas that change
+        let changed = spotlight_info.take_changed_area();
+        if started {
+            self.render_spotlight(None);
+        } else if changed.is_some() {
+            self.render_spotlight(changed);
+        }
+        let painter = ui.painter_at(img.rect);
+        for (rect, shape) in &self.paint_info.spotlight_info.areas {
+            let screen_rect = Rect::from_min_max(into_absolute_pos(rect.min, img.rect, zoom), into_absolute_pos(rect.max, img.rect, zoom));
+            let stroke = Stroke::new(1., Color32::from_white_alpha(180));
+            match shape {
+                SpotShape::Rectangle => painter.rect_stroke(screen_rect, 0., stroke),
+                SpotShape::Ellipse => {
+                    let points = (0..64).map(|i| {
+                        let angle = i as f32 / 64. * std::f32::consts::TAU;
+                        screen_rect.center() + Vec2::new(angle.cos() * screen_rect.width() / 2., angle.sin() * screen_rect.height() / 2.)
+                    }).collect();
+                    painter.add(egui::Shape::closed_line(points, stroke));
+                }
+            }
+        }
+    }
+
+    /// Logic of the insert tool: move and resize the inserted image, Enter confirms it and Escape removes it
+    fn insert_logic(&mut self, img: Response, ui: &mut Ui) {
+        let zoom = self.view.zoom;
+        let tolerance = CROP_HANDLE_SIZE / zoom;
+        let insert_info = &mut self.paint_info.insert_info;
+        let rect = match insert_info.rect {
+            Some(rect) => rect,
+            None => return,
+        };
+        let mut changed = false;
+        if img.drag_started_by(PointerButton::Primary) {
+            if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                let ptr = into_relative_pos(pos, img.rect, zoom);
+                if let Some(target) = insert_info.hit(ptr, tolerance) {
+                    insert_info.start_drag(ptr, target);
+                }
+            }
+        }
+        if img.dragged_by(PointerButton::Primary) {
+            if let Some(pos) = img.interact_pointer_pos() {
+                insert_info.drag(into_relative_pos(pos, img.rect, zoom));
+                changed = true;
+            }
+        } else if img.drag_released_by(PointerButton::Primary) {
+            insert_info.end_drag();
+        }
+        if let Some(pos) = img.hover_pos() {
+            let cursor = match insert_info.dragging.or(insert_info.hit(into_relative_pos(pos, img.rect, zoom), tolerance)) {
+                Some(InsertDrag::Resize) => CursorIcon::ResizeNwSe,
+                Some(InsertDrag::Move) => CursorIcon::Move,
+                None => CursorIcon::Default,
+            };
+            ui.ctx().set_cursor_icon(cursor);
+        }
+        let (confirm, cancel) = ui.input(|i| (i.key_pressed(Key::Enter), i.key_pressed(Key::Escape)));
+        if cancel {
+            let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+            self.paint_info.insert_info.reset();
+            return;
+        }
+        if confirm {
+            self.commit_insert();
+            return;
+        }
+        if changed {
+            self.render_insert();
+        }
+        //Outline and resize handle, drawn on the screen only
+        let rect = self.paint_info.insert_info.rect.unwrap_or(rect);
+        let painter = ui.painter_at(img.rect);
+        let screen_rect = Rect::from_min_max(into_absolute_pos(rect.min, img.rect, zoom), into_absolute_pos(rect.max, img.rect, zoom));
+        painter.rect_stroke(screen_rect, 0., Stroke::new(1., Color32::WHITE));
+        painter.rect_filled(Rect::from_center_size(screen_rect.max, Vec2::splat(CROP_HANDLE_SIZE)), 0., Color32::WHITE);
     }
 
     /// Logic for painting on the image
     fn paint_logic(&mut self, img: Response, ui: &mut Ui, rect: Rect) {
-        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
-        //If the tool is not Text, and text_info is dirty (update tmp_image so that it gets the changes without the temporary cursor and textarrea)
+        //If the tool is not Text, and text_info is dirty, the text is finished and goes in the stack, without the temporary cursor and textarea
         if self.paint_info.curr_tool != Tool::Text && self.paint_info.text_info.dirty {
-            self.paint_info.text_info.reset();
-            //Set tmp img in order to delete the text area
-            curr_screenshot.set_tmp_image(curr_screenshot.get_last_image());
+            self.commit_text();
+        }
+        //Same for a callout or a magnifier left by changing tool
+        if self.paint_info.curr_tool != Tool::Callout && self.paint_info.callout_info.rect.is_some() {
+            self.commit_callout();
+        }
+        if self.paint_info.curr_tool != Tool::Magnifier && self.paint_info.magnifier_info.source.is_some() {
+            self.commit_magnifier();
+        }
+        //An inserted image is kept where it was placed, and so is a spotlight
+        if self.paint_info.curr_tool != Tool::Insert && self.paint_info.insert_info.rect.is_some() {
+            self.commit_insert();
+        }
+        if self.paint_info.curr_tool != Tool::Spotlight && self.paint_info.spotlight_info.active {
+            self.commit_spotlight();
+        }
+        let curr_screenshot = self.curr_screenshot.as_mut().unwrap();
+        let zoom = self.view.zoom;
+        if self.paint_info.curr_tool != Tool::Measure {
+            self.paint_info.measure_info.reset();
         }
         if self.paint_info.curr_tool == Tool::Text && !self.paint_info.text_info.writing {
-            match img.interact_pointer_pos() {
-                Some(ptr) => {
-                    //Initialization for the pop/stack in the following else if
-                    curr_screenshot.stack_image(curr_screenshot.get_last_image());
-                    self.paint_info.text_info.original_img = curr_screenshot.get_last_image();
-                    self.paint_info.text_info.dirty = true;
-                    self.paint_info.text_info.writing = true;
-                    self.paint_info.text_info.edge = into_relative_pos(ptr, img.rect);
-                    self.paint_info.text_info.height = self.paint_info.text_info.curr_dim as f32;
-                    //Compute max_width/max_height when initializing a textarea
-                    self.paint_info.text_info.max_width = curr_screenshot.tmp_image.get_width() as f32 - self.paint_info.text_info.edge.x;
-                    self.paint_info.text_info.max_height = curr_screenshot.tmp_image.get_height() as f32 - self.paint_info.text_info.edge.y;
-                }
-                None => {}
+            if let Some(ptr) = img.interact_pointer_pos().filter(|_| ui.input(|i| i.pointer.primary_down())) {
+                //The text is drawn on this image at every change, and stacked only once it is finished
+                self.paint_info.text_info.original_img = curr_screenshot.get_last_image();
+                self.paint_info.text_info.dirty = true;
+                self.paint_info.text_info.writing = true;
+                self.paint_info.text_info.edge = into_relative_pos(ptr, img.rect, zoom);
+                self.paint_info.text_info.height = self.paint_info.text_info.curr_dim as f32;
+                //Compute max_width/max_height when initializing a textarea
+                self.paint_info.text_info.max_width = curr_screenshot.tmp_image.get_width() as f32 - self.paint_info.text_info.edge.x;
+                self.paint_info.text_info.max_height = curr_screenshot.tmp_image.get_height() as f32 - self.paint_info.text_info.edge.y;
             }
         }
         else if self.paint_info.curr_tool == Tool::Text && self.paint_info.text_info.writing {
@@ -483,31 +2285,16 @@ impl RustShot {
             ui.ctx().request_repaint();
 
             let mut screen_to_paint = self.paint_info.text_info.original_img.clone();
-            let mut screen_to_paint_real = self.paint_info.text_info.original_img.clone();
 
+            //Draw the text, the selection and the current cursor, only as a preview
             self.paint_info.curr_tool = Tool::Text;
-            self.paint_info.apply_tool(&mut screen_to_paint_real, Some(self.paint_info.text_info.original_img.get_image()));
-
-            // This is the real one, without textarea
-            curr_screenshot.pop_last_image();
-            curr_screenshot.stack_image(screen_to_paint_real.clone());
-
-            //Draw current cursor
-            let cursor = self.paint_info.text_info.cursor_x;
-            self.paint_info.text_info.curr_str.insert(cursor, char::from_str("_").unwrap());
-            self.paint_info.apply_tool(&mut screen_to_paint, Some(self.paint_info.text_info.original_img.get_image()));
+            self.paint_info.apply_tool(&mut screen_to_paint, None);
+            self.paint_info.draw_text_cursor(&mut screen_to_paint);
 
             //Retrieve actual width and height of current textarea.
-            let lines: Vec<&str> = self.paint_info.text_info.curr_str.split("\n").collect();
-            self.paint_info.text_info.height = (lines.len() * self.paint_info.text_info.curr_dim as usize) as f32;
-            let mut width = 0.;
-            for l in &lines {
-                let t = measure_line(self.paint_info.text_info.curr_font.as_ref().unwrap(), l, Scale::uniform(self.paint_info.text_info.curr_dim as f32));
-                if t.0 >= width{
-                    width = t.0;
-                }
-            }
-            self.paint_info.text_info.width = width;
+            let lines = self.paint_info.text_info.buffer.lines().len();
+            self.paint_info.text_info.height = (lines * self.paint_info.text_info.curr_dim as usize) as f32;
+            self.paint_info.text_info.width = self.paint_info.text_info.block_width();
 
             //Draw the textarea
             let old_color = self.paint_info.curr_color;
@@ -516,160 +2303,257 @@ impl RustShot {
             self.paint_info.curr_color = [0u8, 0u8, 0u8, 0u8];
             self.paint_info.last_ptr = Pos2::new(self.paint_info.text_info.edge.x - (self.paint_info.text_info.curr_dim/4) as f32, self.paint_info.text_info.edge.y - (self.paint_info.text_info.curr_dim/4) as f32);
             self.paint_info.curr_ptr = Pos2::new(self.paint_info.last_ptr.x + self.paint_info.text_info.width + (self.paint_info.text_info.curr_dim/4 + self.paint_info.text_info.curr_dim/4) as f32, self.paint_info.last_ptr.y + self.paint_info.text_info.height + (self.paint_info.text_info.curr_dim/4 + self.paint_info.text_info.curr_dim/4) as f32);
-            self.paint_info.apply_tool(&mut screen_to_paint, Some(self.paint_info.text_info.original_img.get_image()));
+            self.paint_info.apply_tool(&mut screen_to_paint, None);
             self.paint_info.curr_tool = Tool::Text;
             self.paint_info.curr_color = old_color;
 
-            //Remove the added cursor from the curr_str
-            self.paint_info.text_info.curr_str.remove(cursor);
-
-            curr_screenshot.set_tmp_image(screen_to_paint);
+            //Only the area of the text, where it was and where it is now, has to be shown again
+            let text_info = &mut self.paint_info.text_info;
+            let margin = (text_info.curr_dim as u32 + text_info.style.padding + text_info.style.outline_width) as f32 + 4.;
+            let area = Rect::from_two_pos(self.paint_info.last_ptr, self.paint_info.curr_ptr).expand(margin);
+            let changed = match text_info.preview_area.replace(area) {
+                Some(previous) => previous.union(area),
+                None => area,
+            };
+            curr_screenshot.tmp_image = screen_to_paint;
+            curr_screenshot.mark_tmp_changed(changed);
             //Logic for updating the state of text_info
+            let mut copied_text = None;
             ui.input(|i| {
-                let events = &i.events;
-                for e in events {
+                for e in &i.events {
+                    //Stop adding text once the textarea reaches the border of the image
+                    let too_wide = self.paint_info.text_info.width >= self.paint_info.text_info.max_width;
+                    let too_high = self.paint_info.text_info.height >= self.paint_info.text_info.max_height;
                     match e {
-                        Event::Text(str) => {
-                            if self.paint_info.text_info.width < self.paint_info.text_info.max_width  {
-                                self.paint_info.text_info.curr_str.insert_str(self.paint_info.text_info.cursor_x, str);
-                                self.paint_info.text_info.cursor_x += str.len();
-                            }
-                        }
-                        Event::Key { key, pressed, .. } => {
-                            if let egui::Key::Enter = key  {
-                                println!("height:{}, max_height:{}", self.paint_info.text_info.height, self.paint_info.text_info.max_height);
-                                if *pressed && self.paint_info.text_info.height < self.paint_info.text_info.max_height {
-                                    self.paint_info.text_info.curr_str.insert_str(self.paint_info.text_info.cursor_x, "\n");
-                                    self.paint_info.text_info.cursor_x += 1;
-                                }
-                            }
-                            else if let egui::Key::Backspace = key  {
-                                if *pressed {
-                                    if self.paint_info.text_info.cursor_x > 0{
-                                        self.paint_info.text_info.curr_str.remove(self.paint_info.text_info.cursor_x - 1 );
-                                        self.paint_info.text_info.cursor_x -= 1;
-                                    }
-                                }
-                            }
-                            else if let egui::Key::ArrowRight = key  {
-                                if *pressed && self.paint_info.text_info.cursor_x < self.paint_info.text_info.curr_str.len(){
-                                    self.paint_info.text_info.cursor_x += 1;
-                                }
-                            }
-                            else if let egui::Key::ArrowLeft = key  {
-                                if *pressed && self.paint_info.text_info.cursor_x > 0{
-                                    self.paint_info.text_info.cursor_x -= 1;
-                                }
-                            }
-                            else if let egui::Key::Escape = key {
-                                self.paint_info.curr_tool = Tool::None;
-                            }
-                        }
-
+                        Event::Text(_) | Event::Paste(_) if too_wide => continue,
+                        Event::Key { key: Key::Enter, .. } if too_high => continue,
                         _ => {}
                     }
+                    match self.paint_info.text_info.buffer.handle_event(e) {
+                        TextAction::Copy(text) => copied_text = Some(text),
+                        TextAction::Exit => self.paint_info.curr_tool = Tool::None,
+                        TextAction::None => {}
+                    }
                 }
             }
             );
+            if let Some(text) = copied_text {
+                ui.output_mut(|o| o.copied_text = text);
+            }
             //Stop writing text if click happens somewhere
-             if img.drag_started(){
+             if img.drag_started_by(PointerButton::Primary){
                  self.paint_info.curr_tool = Tool::None;
              }
+        } else if self.paint_info.curr_tool == Tool::Callout {
+            self.callout_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Magnifier {
+            self.magnifier_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Eyedropper {
+            self.eyedropper_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Insert {
+            self.insert_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Measure {
+            self.measure_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Spotlight {
+            self.spotlight_logic(img, ui);
+            return;
+        } else if self.paint_info.curr_tool == Tool::Crop {
+            let (width, height) = (curr_screenshot.tmp_image.get_width(), curr_screenshot.tmp_image.get_height());
+            // Handles are grabbed within a fixed distance on the screen, whatever the zoom
+            let tolerance = CROP_HANDLE_SIZE / zoom;
+            if self.paint_info.crop_info.smart {
+                if img.clicked() {
+                    if let Some(pos) = img.interact_pointer_pos() {
+                        let ptr = into_relative_pos(pos, img.rect, zoom);
+                        if curr_screenshot.smart_crop((ptr.x as u32, ptr.y as u32)) {
+                            self.paint_info.crop_info.reset();
+                            self.paint_info.curr_tool = Tool::None;
+                        } else {
+                            self.paint_info.crop_info.smart_failed = true;
+                        }
+                    }
+                }
+                img.on_hover_cursor(CursorIcon::PointingHand);
+                return;
+            }
+            if img.drag_started_by(PointerButton::Primary) {
+                if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                    self.paint_info.crop_info.start_drag(into_relative_pos(pos, img.rect, zoom), tolerance);
+                }
+            }
+            if img.dragged_by(PointerButton::Primary) {
+                if let Some(pos) = img.interact_pointer_pos() {
+                    let ptr = into_relative_pos(pos, img.rect, zoom);
+                    self.paint_info.crop_info.drag(ptr, width, height);
+                    // Automatic scrolling when the selection reaches the border of the view
+                    // The viewport is expressed in zoomed coordinates, while the pointer in image pixels
+                    let view_ptr = ptr.to_vec2() * zoom;
+                    if view_ptr.x >= rect.right() - 20. {
+                        ui.scroll_with_delta(Vec2::new(rect.right() - 20. - view_ptr.x, 0.));
+                    }
+                    if view_ptr.x <= rect.left() + 20. {
+                        ui.scroll_with_delta(Vec2::new(rect.left() + 20. - view_ptr.x, 0.));
+                    }
+                    if view_ptr.y <= rect.top() + 20. {
+                        ui.scroll_with_delta(Vec2::new(0., rect.top() + 20. - view_ptr.y));
+                    }
+                    if view_ptr.y >= rect.bottom() - 20. {
+                        ui.scroll_with_delta(Vec2::new(0., rect.bottom() - 20. - view_ptr.y));
+                    }
+                    // To make scrolling while cropping more fluid, i need to keep requesting to repaint
+                    ui.ctx().request_repaint();
+                }
+            } else if img.drag_released_by(PointerButton::Primary) {
+                self.paint_info.crop_info.end_drag();
+            }
+            // Darken the area that will be removed, and draw the rectangle with its handles
+            if let Some(crop_rect) = self.paint_info.crop_info.rect {
+                let painter = ui.painter_at(img.rect);
+                let screen_rect = Rect::from_min_max(into_absolute_pos(crop_rect.min, img.rect, zoom), into_absolute_pos(crop_rect.max, img.rect, zoom));
+                let shade = Color32::from_black_alpha(140);
+                painter.rect_filled(Rect::from_x_y_ranges(img.rect.x_range(), img.rect.top()..=screen_rect.top()), 0., shade);
+                painter.rect_filled(Rect::from_x_y_ranges(img.rect.x_range(), screen_rect.bottom()..=img.rect.bottom()), 0., shade);
+                painter.rect_filled(Rect::from_x_y_ranges(img.rect.left()..=screen_rect.left(), screen_rect.y_range()), 0., shade);
+                painter.rect_filled(Rect::from_x_y_ranges(screen_rect.right()..=img.rect.right(), screen_rect.y_range()), 0., shade);
+                painter.rect_stroke(screen_rect, 0., Stroke::new(1., Color32::WHITE));
+                for (_, pos) in self.paint_info.crop_info.handles() {
+                    let handle = Rect::from_center_size(into_absolute_pos(pos, img.rect, zoom), Vec2::splat(CROP_HANDLE_SIZE));
+                    painter.rect_filled(handle, 0., Color32::WHITE);
+                    painter.rect_stroke(handle, 0., Stroke::new(1., Color32::BLACK));
+                }
+            }
+            // Show a resize cursor when hovering the handles
+            if let Some(pos) = img.hover_pos() {
+                let handle = match self.paint_info.crop_info.dragging {
+                    Some(handle) => handle,
+                    None => self.paint_info.crop_info.hit(into_relative_pos(pos, img.rect, zoom), tolerance),
+                };
+                let cursor = match handle {
+                    CropHandle::TopLeft | CropHandle::BottomRight => CursorIcon::ResizeNwSe,
+                    CropHandle::TopRight | CropHandle::BottomLeft => CursorIcon::ResizeNeSw,
+                    CropHandle::Top | CropHandle::Bottom => CursorIcon::ResizeVertical,
+                    CropHandle::Left | CropHandle::Right => CursorIcon::ResizeHorizontal,
+                    CropHandle::Move => CursorIcon::Move,
+                    CropHandle::New => CursorIcon::Crosshair,
+                };
+                ui.ctx().set_cursor_icon(cursor);
+            }
+            return;
         } else {
-            if img.dragged() && self.paint_info.curr_tool != Tool::None {
+            let is_shape = self.paint_info.curr_tool.is_shape();
+            let tolerance = GUIDE_TOLERANCE / zoom;
+            if img.dragged_by(PointerButton::Primary) && self.paint_info.curr_tool != Tool::None {
+                let modifiers = ui.input(|i| i.modifiers);
+                self.paint_info.constrain = modifiers.shift;
+                self.paint_info.from_center = modifiers.alt;
                 if !self.paint_info.painting {
                     match img.hover_pos(){
                         None => {}
                         Some(pos) => {
-                            self.paint_info.last_ptr = into_relative_pos(pos, img.rect);
+                            //Guides of the image the shape is drawn on, found once for every stacked image
+                            if is_shape && self.paint_info.snap.guides {
+                                self.paint_info.guides = curr_screenshot.get_last_guides();
+                            }
+                            let ptr = into_relative_pos(pos, img.rect, zoom);
+                            self.paint_info.last_ptr = if is_shape { self.paint_info.snap_point(ptr, tolerance) } else { ptr };
                             self.paint_info.painting = true;
                         }
                     }
                 }
                 self.paint_info.curr_ptr = match img.hover_pos() {
-                    Some(pos) => into_relative_pos(pos, img.rect),
+                    Some(pos) if is_shape => self.paint_info.snap_point(into_relative_pos(pos, img.rect, zoom), tolerance),
+                    Some(pos) => into_relative_pos(pos, img.rect, zoom),
                     None => self.paint_info.curr_ptr,
                 };
 
-                // Automatic scrolling when using crop tool
-                if self.paint_info.curr_tool == Tool::Crop {
-                    if self.paint_info.curr_ptr.x >= rect.right() - 20. {
-                        ui.scroll_with_delta(Vec2::new(rect.right() - 20. - self.paint_info.curr_ptr.x, 0.));
-                    }
-                    if self.paint_info.curr_ptr.x <= rect.left() + 20. {
-                        ui.scroll_with_delta(Vec2::new(rect.left() + 20. - self.paint_info.curr_ptr.x, 0.));
-                    }
-                    if self.paint_info.curr_ptr.y <= rect.top() + 20. {
-                        ui.scroll_with_delta(Vec2::new(0., rect.top() + 20. - self.paint_info.curr_ptr.y));
-                    }
-                    if self.paint_info.curr_ptr.y >= rect.bottom() - 20. {
-                        ui.scroll_with_delta(Vec2::new(0., rect.bottom() - 20. - self.paint_info.curr_ptr.y));
+                // When using Eraser, i need the latest clean version of the cropped image, when highlighting only the latest version of the image.
+                // They are taken once for the whole stroke, decompressing them at every step is too slow on large images
+                if self.paint_info.stroke_original.is_none() {
+                    self.paint_info.stroke_original = match self.paint_info.curr_tool {
+                        Tool::Eraser if self.paint_info.eraser_mode == EraserMode::Pixels => Some(curr_screenshot.get_clean_layer()),
+                        Tool::Highlighter | Tool::Drawing => Some(curr_screenshot.get_last_image_as_ref().get_decompressed_image()),
+                        _ => None,
+                    };
+                }
+                if self.paint_info.curr_tool == Tool::Drawing {
+                    let time = ui.input(|i| i.time);
+                    if self.paint_info.stroke.is_empty() {
+                        self.paint_info.push_stroke_point(self.paint_info.last_ptr, time);
                     }
-                    // To make scrolling while cropping more fluid, i need to keep requesting to repaint
-                    ui.ctx().request_repaint();
+                    self.paint_info.push_stroke_point(self.paint_info.curr_ptr, time);
                 }
 
-                // When using Eraser, i need the latest clean version of the cropped image, when highlighting only the latest version of the image
-                // Using Option, I avoid useless "clone()" when not needed with other tools, using get_image_as_ref, i avoid other useless "clone()".
-                let tmp = match self.paint_info.curr_tool {
-                    Tool::Eraser => Some(curr_screenshot.get_crop_image(curr_screenshot.get_last_image_as_ref().get_crop_index())),
-                    Tool::Highlighter => Some(curr_screenshot.get_last_image_as_ref().get_decompressed_image()),
-                    _ => None,
-                };
-
+                //Freehand tools draw directly on the image shown, the shapes are only previewed until they are released
                 if self.paint_info.curr_tool == Tool::Drawing || self.paint_info.curr_tool == Tool::Highlighter || self.paint_info.curr_tool == Tool::Eraser {
-                    self.paint_info.apply_tool(&mut curr_screenshot.tmp_image, tmp);
+                    let original = self.paint_info.stroke_original.take();
+                    self.paint_info.apply_tool(&mut curr_screenshot.tmp_image, original.as_ref());
+                    self.paint_info.stroke_original = original;
+                    self.paint_info.extend_stroke_bounds();
                     // This is needed for this tools, that act like continous lines
                     self.paint_info.last_ptr = self.paint_info.curr_ptr;
                 }
-                else {
-                    let mut screen_to_paint = curr_screenshot.get_last_image();
-                    self.paint_info.apply_tool(&mut screen_to_paint, tmp);
-                    curr_screenshot.set_tmp_image(screen_to_paint);
-                }
-            } else if img.drag_released() && self.paint_info.curr_tool != Tool::None {
-                if self.paint_info.curr_tool == Tool::Crop {
-                    //self.paint_info.curr_ptr =
-                    //    into_relative_pos(img.interact_pointer_pos().unwrap(), img.rect);
-                    let width = max(
-                        1,
-                        (self.paint_info.curr_ptr.x - self.paint_info.last_ptr.x).abs() as i32,
-                    );
-                    let height = max(
-                        1,
-                        (self.paint_info.curr_ptr.y - self.paint_info.last_ptr.y).abs() as i32,
-                    );
-                    //Permits an easier selection when cropping, allowing to generate the crop area in all directions
-                    let mut start_ptr = self.paint_info.last_ptr;
-                    if self.paint_info.curr_ptr.x < self.paint_info.last_ptr.x {
-                        start_ptr.x = self.paint_info.curr_ptr.x;
-                    }
-                    if self.paint_info.curr_ptr.y < self.paint_info.last_ptr.y {
-                        start_ptr.y = self.paint_info.curr_ptr.y;
-                    }
-                    let curr_img = curr_screenshot.get_last_image();
-                    let new_screen = curr_img.get_image().crop_imm(
-                        start_ptr.x as u32,
-                        start_ptr.y as u32,
-                        width as u32,
-                        height as u32,
-                    );
-                    let crop_image = curr_screenshot.get_crop_image(curr_img.get_crop_index()).crop_imm(
-                        start_ptr.x as u32,
-                        start_ptr.y as u32,
-                        width as u32,
-                        height as u32,
-                    );
-                    let img = Image::new(new_screen, curr_screenshot.get_crop_images_len());
-                    curr_screenshot.stack_image(img.clone());
-                    curr_screenshot.set_tmp_image(img);
-                    curr_screenshot.push_crop_image(crop_image);
-                    self.paint_info.curr_tool = Tool::None;
-                } else {
-                    curr_screenshot.stack_image(curr_screenshot.get_tmp_image());
+            } else if img.drag_released_by(PointerButton::Primary) && self.paint_info.curr_tool != Tool::None {
+                //The end of a freehand stroke is drawn only now that there are no more samples
+                if self.paint_info.curr_tool == Tool::Drawing {
+                    let original = self.paint_info.stroke_original.take().unwrap_or_else(|| curr_screenshot.get_last_image_as_ref().get_decompressed_image());
+                    self.paint_info.draw_pen_stroke(&mut curr_screenshot.tmp_image, &original, true);
+                }
+                if is_shape && self.paint_info.painting {
+                    self.paint_info.apply_tool(&mut curr_screenshot.tmp_image, None);
+                    if let Some(area) = self.paint_info.shape_area() {
+                        self.paint_info.changed_area = Some(area);
+                    }
+                }
+                //Remember where the annotation is, to snap the next ones to it
+                let before = curr_screenshot.get_last_image();
+                if let Some(bounds) = self.paint_info.annotation_bounds() {
+                    curr_screenshot.tmp_image.add_stroke_annotation(bounds, &before, &self.paint_info.stroke);
+                }
+                curr_screenshot.stack_image(curr_screenshot.get_tmp_image());
+                if self.paint_info.curr_tool != Tool::Eraser {
+                    self.paint_info.colors.add_recent(self.paint_info.curr_color);
                 }
                 self.paint_info.soft_reset();
             }
+            if let Some(area) = self.paint_info.changed_area.take() {
+                curr_screenshot.mark_tmp_changed(area);
+                //The change is shown in the next frame
+                ui.ctx().request_repaint();
+            }
+            let painter = ui.painter_at(img.rect);
+            if is_shape && self.paint_info.painting {
+                draw_shape_preview(&painter, &self.paint_info, img.rect, zoom);
+            }
+            //Grid the shapes snap to, when it is dense enough to be useful but not too much to be seen
+            let grid_step = self.paint_info.snap.grid_size as f32 * zoom;
+            if is_shape && self.paint_info.snap.grid && grid_step >= 6. {
+                let stroke = Stroke::new(1., Color32::from_white_alpha(40));
+                let mut x = img.rect.left();
+                while x <= img.rect.right() {
+                    painter.vline(x, img.rect.y_range(), stroke);
+                    x += grid_step;
+                }
+                let mut y = img.rect.top();
+                while y <= img.rect.bottom() {
+                    painter.hline(img.rect.x_range(), y, stroke);
+                    y += grid_step;
+                }
+            }
+            //Guides the shape being drawn is snapped to
+            let guide_stroke = Stroke::new(1., Color32::from_rgb(255, 0, 200));
+            if self.paint_info.painting {
+                if let Some(x) = self.paint_info.active_guides.0 {
+                    painter.vline(into_absolute_pos(Pos2::new(x, 0.), img.rect, zoom).x, img.rect.y_range(), guide_stroke);
+                }
+                if let Some(y) = self.paint_info.active_guides.1 {
+                    painter.hline(img.rect.x_range(), into_absolute_pos(Pos2::new(0., y), img.rect, zoom).y, guide_stroke);
+                }
+            }
         }
         //Change cursor when using a tool
         match self.paint_info.curr_tool {
@@ -740,6 +2624,17 @@ impl RustShot {
 
 impl App for RustShot {
     fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        //The user fonts folder was changed in the settings
+        if self.shortcuts.fonts_path != self.fonts_path {
+            self.fonts_path = self.shortcuts.fonts_path.clone();
+            self.fonts_receiver = scan_fonts(ctx, self.fonts_path.clone());
+        }
+        if let Ok(fonts) = self.fonts_receiver.try_recv() {
+            self.fonts = fonts;
+            if let Some(name) = self.pending_font.take() {
+                self.select_font(&name);
+            }
+        }
         match self.receiver.try_recv() {
             Ok(screenshot) => {
                 //Show the application window again
@@ -747,6 +2642,9 @@ impl App for RustShot {
                 //let color_image = ColorImage::from_rgb([screenshot.width() as usize, screenshot.height() as usize], screenshot.as_bytes());
                 //self.screenshot = Some(RetainedImage::from_color_image("screenshot", color_image));
                 self.curr_screenshot = Some(ImageStack::new(screenshot));
+                self.export_preview = None;
+                //A new capture is usually as big as the whole screen, show it entirely
+                self.view.fit_to_window = true;
             }
             Err(_) => {}
         }
@@ -760,6 +2658,17 @@ impl App for RustShot {
             println!("tray event: {event:?}");
         }
         self.render_top_panel(ctx, frame);
+        if self.frame_window_open && self.action == Action::None && self.curr_screenshot.is_some() {
+            self.render_frame_window(ctx);
+        }
+        if self.watermark_window_open && self.action == Action::None && self.curr_screenshot.is_some() {
+            self.render_watermark_window(ctx);
+        }
+        //The watermark settings are written to disk once a slider or a text is no longer being dragged or edited
+        if self.watermark_dirty && !ctx.input(|i| i.pointer.any_down()) && !ctx.wants_keyboard_input() {
+            self.shortcuts.save_watermarks();
+            self.watermark_dirty = false;
+        }
         self.render_central_panel(ctx, frame);
         if self.show_confirmation_dialog {
             // Show confirmation dialog:
@@ -1,4 +1,5 @@
-use super::config_mod::KeyCommand;
+use super::config_mod::{KeyCommand, Palette};
+use super::export_mod::WatermarkSettings;
 use eframe::egui::{Button, Context, Key, KeyboardShortcut, Modifiers, Ui, Window, ComboBox};
 use global_hotkey::hotkey::{HotKey, Code};
 use serde::{Deserialize, Serialize};
@@ -118,6 +119,22 @@ pub struct ShortcutManager {
     shortcut_invalid: Option<KeyCommand>,
     pub default_path: Option<PathBuf>,
     pub extension: String,
+    //Folder with the user's own fonts, scanned together with the system ones
+    #[serde(default)]
+    pub fonts_path: Option<PathBuf>,
+    //Font last chosen for the text tool
+    #[serde(default)]
+    pub last_font: Option<String>,
+    //Named color palettes saved by the user
+    #[serde(default)]
+    pub palettes: Vec<Palette>,
+    //Watermark profiles, the selected one is applied on export when enabled
+    #[serde(default)]
+    pub watermarks: Vec<WatermarkSettings>,
+    #[serde(default)]
+    pub watermark_profile: usize,
+    #[serde(default)]
+    pub watermark_enabled: bool,
 }
 
 impl Default for ShortcutManager {
@@ -153,6 +170,12 @@ impl Default for ShortcutManager {
             shortcut_invalid: None,
             default_path: Some(PathBuf::from("./")),
             extension: ".png".to_string(),
+            fonts_path: None,
+            last_font: None,
+            palettes: Vec::new(),
+            watermarks: Vec::new(),
+            watermark_profile: 0,
+            watermark_enabled: false,
         };
     }
 }
@@ -334,6 +357,21 @@ impl ShortcutManager {
                         });
                     });
                     ui.add(egui::Separator::default());
+                    ui.columns(2, |columns| {
+                        match &self.fonts_path {
+                            Some(path) => columns[0].label(format!("Fonts: {}", path.display())),
+                            None => columns[0].label("Fonts: system only"),
+                        };
+                        columns[1].vertical_centered(|ui| {
+                            if ui.add(Button::new("Change fonts folder")).clicked() {
+                                if let Some(path) = FileDialog::new().pick_folder() {
+                                    self.changed_settings = true;
+                                    self.fonts_path = Some(path)
+                                }
+                            }
+                        });
+                    });
+                    ui.add(egui::Separator::default());
                     ui.columns(2, |columns| {
                         columns[0].label(format!("Default Extension"));
                         columns[1].vertical_centered(|ui| {
@@ -369,6 +407,12 @@ impl ShortcutManager {
                                 shortcut_invalid: self.shortcut_invalid.clone(),
                                 default_path: self.default_path.clone(),
                                 extension: self.extension.clone(),
+                                fonts_path: self.fonts_path.clone(),
+                                last_font: self.last_font.clone(),
+                                palettes: self.palettes.clone(),
+                                watermarks: self.watermarks.clone(),
+                                watermark_profile: self.watermark_profile,
+                                watermark_enabled: self.watermark_enabled,
                             };
 
                             match write_to_disk(&new_scm)
@@ -385,6 +429,50 @@ impl ShortcutManager {
             });
     }
 
+    /// Save the font chosen for the text tool, so that it is selected again at the next start
+    pub fn remember_font(&mut self, name: &str) {
+        self.last_font = Some(name.to_string());
+        let last_font = self.last_font.clone();
+        self.update_saved(|saved| saved.last_font = last_font);
+    }
+
+    /// Save the color palettes, they are kept across sessions
+    pub fn save_palettes(&mut self) {
+        let palettes = self.palettes.clone();
+        self.update_saved(|saved| saved.palettes = palettes);
+    }
+
+    /// Save the watermark profiles and which one is used
+    pub fn save_watermarks(&mut self) {
+        let (watermarks, profile, enabled) = (self.watermarks.clone(), self.watermark_profile, self.watermark_enabled);
+        self.update_saved(|saved| {
+            saved.watermarks = watermarks;
+            saved.watermark_profile = profile;
+            saved.watermark_enabled = enabled;
+        });
+    }
+
+    /// Watermark to apply on export, if enabled
+    pub fn active_watermark(&self) -> Option<&WatermarkSettings> {
+        if self.watermark_enabled {
+            self.watermarks.get(self.watermark_profile)
+        } else {
+            None
+        }
+    }
+
+    /// Apply [update] to the saved settings, leaving out the changes in the settings window not applied yet
+    fn update_saved(&self, update: impl FnOnce(&mut ShortcutManager)) {
+        let mut saved = match read_from_disk() {
+            Ok(saved) => saved,
+            Err(_) => ShortcutManager { show_window: false, changed_settings: false, ..self.clone() },
+        };
+        update(&mut saved);
+        if let Err(err) = write_to_disk(&saved) {
+            println!("{}", err);
+        }
+    }
+
     pub fn show_window(&mut self) {
         return self.show_window = true;
     }
@@ -0,0 +1,273 @@
+use std::ops::Range;
+use egui::{Event, Key, Modifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Result of an event handled by a [TextBuffer] that the caller needs to act upon
+pub enum TextAction {
+    None,
+    //The text in the string must be put in the clipboard
+    Copy(String),
+    //The user asked to stop editing
+    Exit,
+}
+
+/// Editable multiline text, with a cursor and an optional selection.
+/// Positions are byte offsets into [text], always kept on grapheme boundaries so that multi-byte characters can't be split
+#[derive(Clone)]
+pub struct TextBuffer {
+    text: String,
+    cursor: usize,
+    //Other end of the selection, the selection goes from [anchor] to [cursor]
+    anchor: Option<usize>,
+    //Column (in graphemes) to go back to when moving up and down across shorter lines
+    preferred_column: Option<usize>,
+}
+
+impl TextBuffer {
+    pub fn new() -> Self {
+        TextBuffer {
+            text: String::new(),
+            cursor: 0,
+            anchor: None,
+            preferred_column: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.anchor = None;
+        self.preferred_column = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        self.text.split('\n').collect()
+    }
+
+    /// Selected byte range, if the selection is not empty
+    pub fn selection(&self) -> Option<Range<usize>> {
+        match self.anchor {
+            Some(anchor) if anchor != self.cursor => Some(anchor.min(self.cursor)..anchor.max(self.cursor)),
+            _ => None,
+        }
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        self.selection().map(|range| self.text[range].to_string())
+    }
+
+    /// Line index and byte offset inside that line of the given position
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line_start = self.line_start(pos);
+        (self.text[..line_start].matches('\n').count(), pos - line_start)
+    }
+
+    /// Line index and byte offset inside that line of the cursor
+    pub fn cursor_line_col(&self) -> (usize, usize) {
+        self.line_col(self.cursor)
+    }
+
+    /// Insert [s] at the cursor, replacing the selection if there is one
+    pub fn insert(&mut self, s: &str) {
+        self.delete_selection();
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+        self.preferred_column = None;
+    }
+
+    /// Delete the selection, or the grapheme before the cursor
+    pub fn backspace(&mut self) {
+        if !self.delete_selection() && self.cursor > 0 {
+            let start = self.prev_boundary(self.cursor);
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+        self.preferred_column = None;
+    }
+
+    /// Delete the selection, or the grapheme after the cursor
+    pub fn delete(&mut self) {
+        if !self.delete_selection() && self.cursor < self.text.len() {
+            let end = self.next_boundary(self.cursor);
+            self.text.replace_range(self.cursor..end, "");
+        }
+        self.preferred_column = None;
+    }
+
+    pub fn select_all(&mut self) {
+        self.anchor = Some(0);
+        self.cursor = self.text.len();
+    }
+
+    /// Remove the selected text, returning it
+    pub fn cut(&mut self) -> Option<String> {
+        let selected = self.selected_text();
+        self.delete_selection();
+        selected
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        match self.selection() {
+            Some(range) => {
+                self.cursor = range.start;
+                self.text.replace_range(range, "");
+                self.anchor = None;
+                true
+            }
+            None => {
+                self.anchor = None;
+                false
+            }
+        }
+    }
+
+    /// Move the cursor to [pos], extending the selection if [select] is true
+    fn move_to(&mut self, pos: usize, select: bool) {
+        if select {
+            if self.anchor.is_none() {
+                self.anchor = Some(self.cursor);
+            }
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = pos;
+    }
+
+    fn prev_boundary(&self, pos: usize) -> usize {
+        self.text[..pos].grapheme_indices(true).next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, pos: usize) -> usize {
+        self.text[pos..].graphemes(true).next().map(|g| pos + g.len()).unwrap_or(self.text.len())
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..].find('\n').map(|i| pos + i).unwrap_or(self.text.len())
+    }
+
+    /// Start of the word before [pos], skipping the whitespace in between
+    fn prev_word(&self, pos: usize) -> usize {
+        let mut result = 0;
+        for (i, word) in self.text[..pos].split_word_bound_indices().rev() {
+            result = i;
+            if !word.trim().is_empty() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// End of the word after [pos], skipping the whitespace in between
+    fn next_word(&self, pos: usize) -> usize {
+        let mut result = self.text.len();
+        for (i, word) in self.text[pos..].split_word_bound_indices() {
+            result = pos + i + word.len();
+            if !word.trim().is_empty() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Move the cursor to the line above or below ([down]), keeping the column if possible
+    fn move_vertically(&mut self, down: bool, select: bool) {
+        let line_start = self.line_start(self.cursor);
+        let column = self.preferred_column.unwrap_or_else(|| self.text[line_start..self.cursor].graphemes(true).count());
+        let target_start = if down {
+            let line_end = self.line_end(self.cursor);
+            if line_end == self.text.len() {
+                self.move_to(self.text.len(), select);
+                return;
+            }
+            line_end + 1
+        } else {
+            if line_start == 0 {
+                self.move_to(0, select);
+                return;
+            }
+            self.line_start(line_start - 1)
+        };
+        let target_end = self.line_end(target_start);
+        let pos = self.text[target_start..target_end]
+            .grapheme_indices(true)
+            .nth(column)
+            .map(|(i, _)| target_start + i)
+            .unwrap_or(target_end);
+        self.move_to(pos, select);
+        self.preferred_column = Some(column);
+    }
+
+    /// Update the buffer according to a keyboard/clipboard event
+    pub fn handle_event(&mut self, event: &Event) -> TextAction {
+        match event {
+            Event::Text(s) => self.insert(s),
+            Event::Paste(s) => self.insert(&s.replace("\r\n", "\n")),
+            Event::Copy => {
+                if let Some(s) = self.selected_text() {
+                    return TextAction::Copy(s);
+                }
+            }
+            Event::Cut => {
+                if let Some(s) = self.cut() {
+                    return TextAction::Copy(s);
+                }
+            }
+            Event::Key { key, pressed: true, modifiers, .. } => return self.handle_key(*key, *modifiers),
+            _ => {}
+        }
+        TextAction::None
+    }
+
+    fn handle_key(&mut self, key: Key, modifiers: Modifiers) -> TextAction {
+        let select = modifiers.shift;
+        let word = modifiers.command || modifiers.alt;
+        let vertical = key == Key::ArrowUp || key == Key::ArrowDown;
+        match key {
+            Key::Enter => self.insert("\n"),
+            Key::Backspace => self.backspace(),
+            Key::Delete => self.delete(),
+            Key::ArrowLeft => {
+                let pos = match self.selection() {
+                    //Without shift, collapse the selection on its start
+                    Some(range) if !select => range.start,
+                    _ if word => self.prev_word(self.cursor),
+                    _ => self.prev_boundary(self.cursor),
+                };
+                self.move_to(pos, select);
+            }
+            Key::ArrowRight => {
+                let pos = match self.selection() {
+                    Some(range) if !select => range.end,
+                    _ if word => self.next_word(self.cursor),
+                    _ => self.next_boundary(self.cursor),
+                };
+                self.move_to(pos, select);
+            }
+            Key::ArrowUp => self.move_vertically(false, select),
+            Key::ArrowDown => self.move_vertically(true, select),
+            Key::Home => {
+                let pos = if modifiers.command { 0 } else { self.line_start(self.cursor) };
+                self.move_to(pos, select);
+            }
+            Key::End => {
+                let pos = if modifiers.command { self.text.len() } else { self.line_end(self.cursor) };
+                self.move_to(pos, select);
+            }
+            Key::A if modifiers.command => self.select_all(),
+            Key::Escape => return TextAction::Exit,
+            _ => {}
+        }
+        if !vertical {
+            self.preferred_column = None;
+        }
+        TextAction::None
+    }
+}